@@ -0,0 +1,172 @@
+//! Reads the shared AWS configuration in `~/.aws/config` and `~/.aws/credentials`,
+//! so the s3 output picks up the same profiles as the AWS CLI and SDKs.
+//!
+//! Only static keys are supported, profiles that assume roles or use SSO have to be resolved by other tools.
+
+use std::{collections::HashMap, env, fs, io, path::PathBuf};
+
+use color_eyre::{eyre::eyre, Result};
+use s3::creds::Credentials;
+
+/// The settings of a single profile.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub region: Option<String>,
+    pub endpoint: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    pub session_token: Option<String>,
+}
+
+impl Profile {
+    /// Loads the profile `name`, or [None] if neither file mentions it.
+    ///
+    /// The files can be moved with `AWS_CONFIG_FILE` and `AWS_SHARED_CREDENTIALS_FILE`, like for the AWS CLI.
+    pub fn load(name: &str) -> Result<Option<Self>> {
+        let config = read("AWS_CONFIG_FILE", "config")?;
+        let credentials = read("AWS_SHARED_CREDENTIALS_FILE", "credentials")?;
+        Ok(Self::parse(name, &config, &credentials))
+    }
+
+    /// Combines the profile `name` from the contents of both files,
+    /// settings in the credentials file win.
+    fn parse(name: &str, config: &str, credentials: &str) -> Option<Self> {
+        // the config file prefixes every profile but the default one
+        let config_section = if name == "default" {
+            name.to_string()
+        } else {
+            format!("profile {name}")
+        };
+        let mut settings = sections(config).remove(&config_section);
+        if let Some(credentials) = sections(credentials).remove(name) {
+            settings
+                .get_or_insert_with(HashMap::new)
+                .extend(credentials);
+        }
+
+        settings.map(|mut settings| Self {
+            region: settings.remove("region"),
+            endpoint: settings.remove("endpoint_url"),
+            access_key: settings.remove("aws_access_key_id"),
+            secret_key: settings.remove("aws_secret_access_key"),
+            session_token: settings.remove("aws_session_token"),
+        })
+    }
+
+    /// The static credentials of the profile, if it has any.
+    pub fn credentials(&self) -> Result<Option<Credentials>> {
+        let (Some(access_key), Some(secret_key)) = (&self.access_key, &self.secret_key) else {
+            return Ok(None);
+        };
+        Ok(Some(Credentials::new(
+            Some(access_key),
+            Some(secret_key),
+            None,
+            self.session_token.as_deref(),
+            None,
+        )?))
+    }
+}
+
+/// The contents of `~/.aws/{file}`, or of the file in `variable`. Missing files are empty.
+fn read(variable: &str, file: &str) -> Result<String> {
+    let path = match env::var_os(variable) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let Some(home) = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) else {
+                return Ok(String::new());
+            };
+            PathBuf::from(home).join(".aws").join(file)
+        }
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(eyre!("couldn't read {}: {e}", path.display())),
+    }
+}
+
+/// The `key = value` pairs of every `[section]`.
+///
+/// Indented lines continue nested settings like `s3 =`, which only matter to the SDKs, and are skipped.
+fn sections(source: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = None;
+    for line in source.lines() {
+        if line.starts_with([' ', '\t']) {
+            continue;
+        }
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+            current = Some(name.clone());
+            sections.entry(name).or_default();
+        } else if let (Some(section), Some((key, value))) = (&current, line.split_once('=')) {
+            let value = value.trim();
+            if !value.is_empty() {
+                sections
+                    .entry(section.clone())
+                    .or_default()
+                    .insert(key.trim().to_lowercase(), value.into());
+            }
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+[default]
+region = eu-central-1
+
+[profile minio]
+region=us-east-1
+endpoint_url = http://localhost:9000
+s3 =
+  addressing_style = path
+# a comment
+";
+
+    const CREDENTIALS: &str = "
+[default]
+aws_access_key_id = AKIA
+aws_secret_access_key = secret
+
+[minio]
+aws_access_key_id = minioadmin
+aws_secret_access_key = minioadmin
+";
+
+    #[test]
+    fn profiles() {
+        assert_eq!(
+            Profile::parse("default", CONFIG, CREDENTIALS),
+            Some(Profile {
+                region: Some("eu-central-1".into()),
+                access_key: Some("AKIA".into()),
+                secret_key: Some("secret".into()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Profile::parse("minio", CONFIG, CREDENTIALS),
+            Some(Profile {
+                region: Some("us-east-1".into()),
+                endpoint: Some("http://localhost:9000".into()),
+                access_key: Some("minioadmin".into()),
+                secret_key: Some("minioadmin".into()),
+                session_token: None,
+            })
+        );
+        assert_eq!(Profile::parse("missing", CONFIG, CREDENTIALS), None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Writes the text of every card as CSV and Markdown, for proofreading and translating decks
+//! without looking at the rendered sheets.
+
+use std::{
+    fmt::{self, Write as _},
+    fs,
+    path::Path,
+};
+
+use carp::{COLUMNS, ROWS};
+use color_eyre::{eyre::Context, Result};
+
+use crate::format::{Card, Deck, Markup, Theme};
+
+/// Writes `catalog.csv` and `catalog.md` into `directory`.
+pub fn write(directory: &Path, decks: &[Deck]) -> Result<()> {
+    fs::create_dir_all(directory)?;
+    for (name, contents) in [
+        ("catalog.csv", csv(decks)?),
+        ("catalog.md", markdown(decks)?),
+    ] {
+        let path = directory.join(name);
+        fs::write(&path, contents).with_context(|| format!("couldn't write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Where a card ends up in the sheets, counting from 1.
+struct Position {
+    page: usize,
+    row: usize,
+    column: usize,
+}
+
+impl Position {
+    fn of(index: usize) -> Self {
+        let per_page = (ROWS * COLUMNS) as usize;
+        let columns = COLUMNS as usize;
+        Self {
+            page: index / per_page + 1,
+            row: index % per_page / columns + 1,
+            column: index % columns + 1,
+        }
+    }
+}
+
+fn theme(theme: &Theme) -> &'static str {
+    match theme {
+        Theme::Light => "light",
+        Theme::Dark => "dark",
+    }
+}
+
+/// The text on the top of the card.
+fn top(card: &Card) -> String {
+    card.content
+        .iter()
+        .filter(|markup| !matches!(markup, Markup::Bottom(_)))
+        .map(ToString::to_string)
+        .collect::<String>()
+        .replace('\u{ad}', "")
+}
+
+/// The text on the bottom of the card.
+fn bottom(card: &Card) -> String {
+    card.content
+        .iter()
+        .filter_map(|markup| match markup {
+            Markup::Bottom(content) => Some(content),
+            _ => None,
+        })
+        .flatten()
+        .map(ToString::to_string)
+        .collect::<String>()
+        .replace('\u{ad}', "")
+}
+
+/// One row per card.
+pub fn csv(decks: &[Deck]) -> Result<String, fmt::Error> {
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.into()
+        }
+    }
+
+    let mut csv = String::from("deck,theme,back,index,page,row,column,top,bottom\r\n");
+    for deck in decks {
+        for (index, card) in deck.cards.iter().enumerate() {
+            let Position { page, row, column } = Position::of(index);
+            write!(
+                csv,
+                "{},{},{},{},{page},{row},{column},{},{}\r\n",
+                field(&deck.name),
+                theme(&deck.theme),
+                deck.back,
+                index + 1,
+                field(&top(card)),
+                field(&bottom(card)),
+            )?;
+        }
+    }
+    Ok(csv)
+}
+
+/// A section per deck with a numbered list of its cards.
+///
+/// Card text keeps its `*italic*` markup, blanks and other characters Markdown would pick up are escaped.
+pub fn markdown(decks: &[Deck]) -> Result<String, fmt::Error> {
+    fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if matches!(c, '\\' | '_' | '#' | '<' | '>' | '[' | ']' | '`') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    /// Indents continuation lines so they stay inside the list item.
+    fn indent(text: &str) -> String {
+        text.lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n   ")
+    }
+
+    let mut md = String::from("# Catalog\n");
+    for deck in decks {
+        writeln!(md, "\n## {}\n", escape(&deck.name))?;
+        writeln!(md, "- theme: {}", theme(&deck.theme))?;
+        writeln!(md, "- back: {}", deck.back)?;
+        writeln!(md, "- cards: {}\n", deck.cards.len())?;
+
+        for (index, card) in deck.cards.iter().enumerate() {
+            let Position { page, row, column } = Position::of(index);
+            writeln!(md, "{}. {}", index + 1, indent(&escape(&top(card))))?;
+            let bottom = bottom(card);
+            if !bottom.is_empty() {
+                writeln!(md, "\n   Bottom: {}", indent(&escape(&bottom)))?;
+            }
+            writeln!(md, "\n   *Sheet {page}, row {row}, column {column}*\n")?;
+        }
+    }
+    Ok(md)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DECK: &str = r#"<deck name="Questions, mostly" back="unique">
+        <card>Why <i>is</i> there <blank/>?<bottom>Pick "two"</bottom></card>
+        <card>snake_case</card>
+    </deck>"#;
+
+    #[test]
+    fn positions() {
+        let last = Position::of(69);
+        assert_eq!((last.page, last.row, last.column), (1, 7, 10));
+        let next = Position::of(70);
+        assert_eq!((next.page, next.row, next.column), (2, 1, 1));
+    }
+
+    #[test]
+    fn catalog() {
+        let deck: Deck = DECK.try_into().unwrap();
+        let decks = [deck];
+
+        assert_eq!(
+            csv(&decks).unwrap(),
+            "deck,theme,back,index,page,row,column,top,bottom\r\n\
+            \"Questions, mostly\",light,unique,1,1,1,1,Why *is* there ____?,\"Pick \"\"two\"\"\"\r\n\
+            \"Questions, mostly\",light,unique,2,1,1,2,snake_case,\r\n"
+        );
+
+        let md = markdown(&decks).unwrap();
+        assert!(md.contains("## Questions, mostly\n"));
+        assert!(md.contains("1. Why *is* there \\_\\_\\_\\_?\n\n   Bottom: Pick \"two\"\n"));
+        assert!(md.contains("2. snake\\_case\n\n   *Sheet 1, row 1, column 2*\n"));
+    }
+}
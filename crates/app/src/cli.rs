@@ -1,18 +1,36 @@
-//! This module contains some Impls for the [Output] subcommand enum.
+//! This module contains the command line arguments and builds exporters for the [Output]s they ask for.
 
+use crate::{aws::Profile, format::Deck, serve::Server};
 use carp::{
-    dimensions::AspectRatio,
-    export::{Export, FileExporter},
+    artifact::Artifact,
+    dimensions::{AspectRatio, Dimensions},
+    export::{Export, FanOut, FileExporter, Gallery, Manifest, Store, Template},
+    piet_common::{Color, ImageBuf},
+    renderer::Render,
+    singles::Singles,
+    tts::TTS,
 };
 use carp::{BASE_ASPECT_RATIO, BASE_RESOLUTION};
-use carp_export_s3::S3Exporter;
-use clap::{Parser, Subcommand};
+use carp_export_http::HttpExporter;
+use carp_export_jpeg::JPEGExporter;
+use carp_export_png::{Alpha, CompressionLevel, Filter, Mode, PNGExporter, Strategy};
+use carp_export_s3::{Cleanup, S3Exporter};
+use carp_export_webp::WebPExporter;
+use carp_export_zip::ZipExporter;
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser, ValueEnum};
 use color_eyre::{
     eyre::{eyre, Context},
     Help, Result,
 };
+use log::warn;
 use s3::{creds::Credentials, Bucket, Region};
-use std::{fs, path::PathBuf, str::FromStr};
+use std::{
+    env, fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -31,6 +49,31 @@ pub(crate) struct Args {
     #[arg(short, long, env, default_value_t = BASE_RESOLUTION)]
     pub resolution: u32,
 
+    /// Also export smaller copies of every image, e.g. `--previews 256,1024`.
+    ///
+    /// Each size is the length of the longer side in pixels.
+    #[arg(long, env, value_delimiter = ',', value_parser = clap::value_parser!(u32).range(1..))]
+    pub previews: Vec<u32>,
+
+    /// How the cards are arranged into images.
+    #[arg(short, long, env, value_enum, default_value_t = Layout::Tts)]
+    pub layout: Layout,
+
+    /// The image format of the exported sheets.
+    #[arg(short, long, env, value_enum, default_value_t = Encoding::Png)]
+    pub encoding: Encoding,
+
+    /// The quality of lossy encodings, from 1 (smallest files) to 100 (best quality).
+    #[arg(short, long, env, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+
+    /// Flatten transparent parts of the images onto this color, e.g. #ffffff.
+    ///
+    /// JPEGs are always flattened, onto white unless this is set.
+    /// PNGs are only flattened if this is set, which makes them smaller.
+    #[arg(short, long, env, value_parser = parse_color)]
+    pub background: Option<Color>,
+
     /// Whether to sync the deck into the Tabletop Simulator.
     #[arg(short, long, default_value_t = false)]
     pub sync_to_tts: bool,
@@ -39,144 +82,841 @@ pub(crate) struct Args {
     #[arg(short, long, env, default_value = "input")]
     pub input: Vec<PathBuf>,
 
+    /// Write timings and file sizes of the run as JSON to this file.
+    #[arg(long, env)]
+    pub metrics: Option<PathBuf>,
+
+    /// Write the text of every card as catalog.csv and catalog.md into this directory.
+    #[arg(long, env)]
+    pub catalog: Option<PathBuf>,
+
+    /// Also store a manifest.json listing every exported file and which deck it belongs to.
+    #[arg(long, env, default_value_t = false)]
+    pub manifest: bool,
+
+    /// Also store an index.html showing every deck with its sheets and the text of its cards.
+    #[arg(long, env, default_value_t = false)]
+    pub gallery: bool,
+
+    /// Show every card on its own in the gallery, cropped out of the sheets.
+    #[arg(long, env, default_value_t = false, requires = "gallery")]
+    pub gallery_crop: bool,
+
+    /// Name exported files after a hash of their content instead of a fresh name for every upload.
+    ///
+    /// Identical images end up with the same name, so shared links stay valid until the image changes.
+    /// This replaces --name-template.
+    #[arg(long, env, default_value_t = false)]
+    pub content_addressed: bool,
+
     /// Where should the deck be exported to?
-    #[command(subcommand)]
-    pub output: Option<Output>,
+    ///
+    /// Can be given multiple times to export to several places at once.
+    #[arg(short, long, value_enum, default_values_t = [Output::Disk])]
+    pub output: Vec<Output>,
+
+    #[command(flatten)]
+    pub print: Print,
+
+    #[command(flatten)]
+    pub png: Png,
+
+    #[command(flatten)]
+    pub disk: Disk,
+
+    #[command(flatten)]
+    pub s3: S3,
+
+    #[command(flatten)]
+    pub http: Http,
+
+    #[command(flatten)]
+    pub serve: Serve,
+
+    #[command(flatten)]
+    pub zip: Zip,
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Layout {
+    /// Sheets of 70 cards, the way the Tabletop Simulator expects them.
+    Tts,
+    /// Every card front and back on its own, for print-on-demand services.
+    Cards,
+}
+
+impl carp::layout::Layout for Layout {
+    fn build<'a, Format, Deck, Card>(
+        &'a self,
+        deck: &'a Deck,
+        renderer: &'a impl Render<Output = Format>,
+    ) -> impl Iterator<Item = carp::Result<Artifact<Format>>> + 'a
+    where
+        Format: 'a,
+        Deck: carp::Deck<Card>,
+        Card: carp::Card<Deck = Deck> + 'a,
+    {
+        match self {
+            Layout::Tts => Box::new(TTS.build(deck, renderer)) as Box<dyn Iterator<Item = _>>,
+            Layout::Cards => Box::new(Singles.build(deck, renderer)),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Print")]
+pub(crate) struct Print {
+    /// The resolution of the card images in the cards layout, in dots per inch.
+    #[arg(long, env, default_value_t = 300)]
+    pub dpi: u32,
+
+    /// The width of a printed card in inches. The height follows from the aspect ratio.
+    #[arg(long, env, default_value_t = 2.5)]
+    pub card_width: f64,
+
+    /// How many inches of image to add around every card, to be cut off when printing.
+    #[arg(long, env, default_value_t = 0.125)]
+    pub bleed: f64,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    /// Lossless, keeps transparency.
+    Png,
+    /// Lossy, transparent parts become white.
+    Jpeg,
+    /// Lossy, keeps transparency.
+    Webp,
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    Color::from_hex_str(s).map_err(|e| format!("{e:?}"))
+}
+
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "PNG")]
+pub(crate) struct Png {
+    /// How hard to compress PNGs. Higher levels make smaller files, but take longer.
+    #[arg(long, env, value_enum, default_value_t = PngCompression::Default)]
+    pub png_compression: PngCompression,
+
+    /// The filter applied to the rows of the image before compressing them.
+    #[arg(long, env, value_enum, default_value_t = PngFilter::Adaptive)]
+    pub png_filter: PngFilter,
+
+    /// The deflate strategy used for compressing.
+    #[arg(long, env, value_enum, default_value_t = PngStrategy::Adaptive)]
+    pub png_strategy: PngStrategy,
+
+    /// How many threads encode a single PNG. Uses every core if not set.
+    #[arg(long, env)]
+    pub png_threads: Option<usize>,
+
+    /// Don't write the deck name, sheet layout and a hash of the image into the PNGs.
+    #[arg(long, env, default_value_t = false)]
+    pub png_strip_metadata: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PngCompression {
+    Fast,
+    Default,
+    High,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PngFilter {
+    /// Pick the best filter for each row.
+    Adaptive,
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PngStrategy {
+    /// Pick a strategy based on the filter.
+    Adaptive,
+    Default,
+    Filtered,
+    HuffmanOnly,
+    Rle,
+    Fixed,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) enum Output {
     /// Export the deck to a directory.
-    Disk {
-        #[arg(short, long, default_value = "export", env)]
-        directory: PathBuf,
-
-        /// Whether to create the directory if it doesn't exist.
-        #[arg(long)]
-        create: bool,
-    },
+    Disk,
     /// Upload the deck into an S3 (compatible) bucket.
-    S3 {
-        /// The name of the S3 bucket to export to.
-        ///
-        /// The bucket must exist and you must have the folliwng permissions:
-        /// - `s3:GetBucketLocation`
-        /// - `s3:PutObject`
-        ///
-        /// Credentials are read from the environment variables `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
-        #[arg(long, env)]
-        s3_bucket: String,
-
-        #[arg(long, env)]
-        s3_region: String,
-
-        /// The S3 endpoint to use. If not set, the default endpoint for the region is used.
-        /// In Minio this setting is called "Server Location".
-        #[arg(long, env)]
-        s3_endpoint: Option<String>,
-
-        /// Whether to use the path style or the subdomain style for S3 URLs.
-        ///
-        /// Minio uses the path style per default, AWS uses the subdomain style.
-        #[arg(long, env, default_value_t = false)]
-        s3_path_style: bool,
-    },
-}
-
-impl Default for Output {
-    fn default() -> Self {
-        Output::Disk {
-            directory: PathBuf::from("export"),
-            create: true,
+    S3,
+    /// Upload the deck to a web server or WebDAV share, like Nextcloud, with HTTP PUT.
+    Http,
+    /// Export the deck to --directory like disk and serve it over HTTP until stopped,
+    /// so every player in the LAN can load it in the Tabletop Simulator.
+    Serve,
+    /// Bundle the deck into a zip archive, together with a manifest.
+    Zip,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Disk")]
+pub(crate) struct Disk {
+    /// The directory to export to.
+    #[arg(short, long, default_value = DEFAULT_DIRECTORY, env)]
+    pub directory: PathBuf,
+
+    /// Whether to create the directory if it doesn't exist.
+    ///
+    /// The default directory is always created, unless --directory is given.
+    #[arg(long)]
+    pub create: bool,
+
+    /// Whether --directory was left at its default, see [`Args::parse_command_line`].
+    #[arg(skip)]
+    pub default_directory: bool,
+
+    /// How to name the exported files.
+    ///
+    /// Available placeholders are {deck}, {side}, {content}, {amount}, {hash}, {preview} and {ext}.
+    /// Slashes create subdirectories.
+    ///
+    /// With --previews the template needs {preview} or {hash}, so the previews don't overwrite the full size images.
+    #[arg(long, env, default_value_t = Template::default())]
+    pub name_template: Template,
+
+    /// Put the files of each deck into a directory named after the deck.
+    #[arg(long, env, default_value_t = false)]
+    pub subdirectories: bool,
+
+    /// Remove the files from the directory that are named like exported files, but weren't exported in this run.
+    ///
+    /// Other files are kept, and nothing is removed if anything in the run failed.
+    #[arg(long, env, default_value_t = false)]
+    pub prune: bool,
+}
+
+const DEFAULT_DIRECTORY: &str = "export";
+
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Zip")]
+pub(crate) struct Zip {
+    /// The archive to write. It's replaced once the run finished without failures,
+    /// otherwise the new archive is written next to it, e.g. as export.incomplete.zip.
+    #[arg(long, env, default_value = "export.zip")]
+    pub zip_file: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "S3")]
+pub(crate) struct S3 {
+    /// The name of the S3 bucket to export to.
+    ///
+    /// The bucket must exist and you must have the folliwng permissions:
+    /// - `s3:GetBucketLocation`
+    /// - `s3:PutObject`
+    /// - `s3:PutObjectAcl`, if --s3-acl is set
+    /// - `s3:DeleteObject`, to clean up after the preflight check
+    ///
+    /// The uploaded objects must be readable by anyone, for the Tabletop Simulator to load them,
+    /// unless --s3-presign-hours is set.
+    ///
+    /// Credentials are read from the profile set with --s3-profile, else from the environment variables
+    /// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`, else from the default profile.
+    #[arg(long, env)]
+    pub s3_bucket: Option<String>,
+
+    /// The profile in ~/.aws/credentials and ~/.aws/config to take the credentials, region and endpoint from.
+    ///
+    /// Only profiles with static keys are supported.
+    #[arg(long, env = "AWS_PROFILE")]
+    pub s3_profile: Option<String>,
+
+    /// The region of the bucket. Falls back to `AWS_REGION`, `AWS_DEFAULT_REGION` and the region of the profile.
+    #[arg(long, env)]
+    pub s3_region: Option<String>,
+
+    /// The S3 endpoint to use. If not set, the default endpoint for the region is used.
+    /// In Minio this setting is called "Server Location".
+    ///
+    /// Falls back to `AWS_ENDPOINT_URL_S3`, `AWS_ENDPOINT_URL` and the `endpoint_url` of the profile.
+    #[arg(long, env)]
+    pub s3_endpoint: Option<String>,
+
+    /// Whether to use the path style or the subdomain style for S3 URLs.
+    ///
+    /// Minio uses the path style per default, AWS uses the subdomain style.
+    #[arg(long, env, default_value_t = false)]
+    pub s3_path_style: bool,
+
+    /// Put in front of every object key, e.g. `decks/` to keep the uploads out of the bucket root.
+    #[arg(long, env, default_value = "")]
+    pub s3_prefix: String,
+
+    /// How the objects are named, see --name-template.
+    ///
+    /// Without a template every upload gets a new unique name.
+    #[arg(long, env)]
+    pub s3_name_template: Option<Template>,
+
+    /// The Cache-Control header the objects are served with, e.g. "public, max-age=31536000, immutable".
+    ///
+    /// Only cache forever in combination with --content-addressed or unique names,
+    /// objects named after their deck are overwritten by every run.
+    #[arg(long, env)]
+    pub s3_cache_control: Option<String>,
+
+    /// The canned ACL of the uploaded objects. Buckets that block public ACLs reject public-read.
+    #[arg(long, env, value_parser = [
+        "private",
+        "public-read",
+        "public-read-write",
+        "authenticated-read",
+        "bucket-owner-read",
+        "bucket-owner-full-control",
+    ])]
+    pub s3_acl: Option<String>,
+
+    /// Don't check that uploads work and are publicly readable before rendering.
+    ///
+    /// The check uploads, downloads and deletes a small probe object.
+    #[arg(long, env, default_value_t = false)]
+    pub s3_skip_preflight: bool,
+
+    /// Don't upload images that are already in the bucket with the same content.
+    ///
+    /// Checks every object with a HEAD request first.
+    /// Without --s3-name-template, objects are named after their content.
+    #[arg(long, env, default_value_t = false)]
+    pub s3_skip_unchanged: bool,
+
+    /// Delete the objects of the exported decks that this run doesn't use anymore.
+    ///
+    /// Which deck uploaded which objects is kept track of in uploads.json in the bucket,
+    /// objects uploaded before that aren't deleted. Needs the permissions `s3:GetObject` and `s3:DeleteObject`.
+    ///
+    /// Nothing is deleted after a run with failures, the old objects may be the last good ones.
+    #[arg(long, env, default_value_t = false)]
+    pub s3_cleanup: bool,
+
+    /// Keep objects this many days after the last run that used them, so shared links keep working for a while.
+    #[arg(long, env, default_value_t = 7.0, requires = "s3_cleanup")]
+    pub s3_retention_days: f64,
+
+    /// Only list what --s3-cleanup would delete.
+    #[arg(long, env, default_value_t = false, requires = "s3_cleanup")]
+    pub s3_cleanup_dry_run: bool,
+
+    /// Use presigned URLs that expire after this many hours instead of public ones, for private buckets.
+    ///
+    /// The Tabletop Simulator and the gallery can load the images until the URLs expire,
+    /// S3 allows at most 168 hours (7 days).
+    #[arg(long, env, value_parser = clap::value_parser!(u64).range(1..=168))]
+    pub s3_presign_hours: Option<u64>,
+
+    /// How often failed uploads are retried, waiting twice as long before every retry.
+    #[arg(long, env, default_value_t = 4)]
+    pub s3_retries: u32,
+
+    /// Images larger than this many MiB are uploaded in parts of this size, at least 5.
+    #[arg(long, env, default_value_t = 16, value_parser = clap::value_parser!(u32).range(5..))]
+    pub s3_part_size: u32,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "HTTP")]
+pub(crate) struct Http {
+    /// The URL to upload below, e.g. https://cloud.example.com/remote.php/dav/files/me/decks/.
+    ///
+    /// The server must accept PUT requests and serve the uploads to everyone who loads the deck,
+    /// unless --http-public-url points somewhere that does.
+    #[arg(long, env)]
+    pub http_url: Option<String>,
+
+    /// The URL the uploads are served below, if it isn't --http-url.
+    ///
+    /// E.g. the public directory of a web server that the WebDAV share points into.
+    #[arg(long, env)]
+    pub http_public_url: Option<String>,
+
+    /// The username for basic authentication.
+    #[arg(long, env)]
+    pub http_username: Option<String>,
+
+    /// The password for basic authentication. Nextcloud needs an app password here.
+    #[arg(long, env, requires = "http_username")]
+    pub http_password: Option<String>,
+
+    /// Create missing directories with WebDAV (MKCOL) before the first upload into them.
+    ///
+    /// Otherwise they're created once an upload fails because its directory is missing.
+    #[arg(long, env, default_value_t = false)]
+    pub http_webdav: bool,
+
+    /// How the files are named, see --name-template. Slashes create directories.
+    #[arg(long, env, default_value_t = Template::default())]
+    pub http_name_template: Template,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Serve")]
+pub(crate) struct Serve {
+    /// The address to listen on. 0.0.0.0 accepts requests from the whole LAN, 127.0.0.1 only from this machine.
+    #[arg(long, env, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    pub serve_address: IpAddr,
+
+    /// The port to listen on, 0 picks a free one.
+    #[arg(long, env, default_value_t = 8080)]
+    pub serve_port: u16,
+
+    /// The host name or address in the URLs, if the other players can't reach the detected LAN address.
+    #[arg(long, env)]
+    pub serve_host: Option<String>,
+}
+
+/// An encoder for the chosen [Encoding].
+pub(crate) type Encoder = Box<dyn Export<Data = ImageBuf, Output = Vec<u8>>>;
+
+/// An exporter for a single [Output].
+pub(crate) type Exporter = Box<dyn Store<Data = Vec<u8>, Output = PathBuf>>;
+
+impl Args {
+    /// Parses the command line like [`Parser::parse`],
+    /// and notes whether the directory was left at its default, which is created without --create.
+    pub fn parse_command_line() -> Self {
+        let matches = Self::command().get_matches();
+        let mut args = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        args.disk.default_directory =
+            matches.value_source("directory") == Some(ValueSource::DefaultValue);
+        args
+    }
+
+    /// The size of the images for the chosen [Layout].
+    pub fn dimensions(&self) -> Dimensions {
+        match self.layout {
+            Layout::Tts => Dimensions::new(self.resolution, self.aspect_ratio),
+            Layout::Cards => Dimensions::print(
+                self.aspect_ratio,
+                self.print.dpi,
+                self.print.card_width,
+                self.print.bleed,
+            ),
         }
     }
-}
 
-impl Output {
-    pub fn exporter(self) -> Result<Box<dyn Export<Data = Vec<u8>, Output = PathBuf>>> {
-        match self {
-            Output::Disk { directory, create } => {
-                if create {
-                    fs::create_dir_all(&directory)?;
-                } else if !directory.is_dir() {
-                    Err(eyre!(
-                        "the directory {:?} doesn't exist {}. Use --create to create it",
-                        directory,
-                        if directory.is_relative() {
-                            format!("in {}", std::env::current_dir()?.display())
-                        } else {
-                            String::new()
+    /// Builds the encoder for the chosen [Encoding].
+    pub fn encoder(&self) -> Result<Encoder> {
+        Ok(match self.encoding {
+            Encoding::Png => Box::new(self.png.exporter(self.background)?),
+            Encoding::Jpeg => {
+                let mut exporter = JPEGExporter::new(self.quality);
+                if let Some(background) = self.background {
+                    exporter.background = background;
+                }
+                Box::new(exporter)
+            }
+            Encoding::Webp => Box::new(WebPExporter::new(self.quality)),
+        })
+    }
+
+    /// Listens for requests for the [Output::Serve] output, if it was asked for.
+    pub fn server(&self) -> Result<Option<Server>> {
+        if !self.output.contains(&Output::Serve) {
+            return Ok(None);
+        }
+        let address = SocketAddr::new(self.serve.serve_address, self.serve.serve_port);
+        Server::bind(
+            address,
+            self.serve.serve_host.as_deref(),
+            self.disk.directory.clone(),
+        )
+        .with_context(|| format!("couldn't listen on {address}"))
+        .suggestion("pick another port with --serve-port")
+        .map(Some)
+    }
+
+    /// Builds an exporter that writes to every [Output] that was asked for.
+    ///
+    /// The `decks` are only needed to list the card text in the gallery,
+    /// the `server` is the one from [`Args::server`].
+    pub fn exporter(&self, decks: &[Deck], server: Option<&Server>) -> Result<FanOut<Exporter>> {
+        let mut outputs = self.output.clone();
+        outputs.sort_unstable();
+        outputs.dedup();
+        // serve writes the same files as disk
+        if outputs.contains(&Output::Serve) {
+            outputs.retain(|output| *output != Output::Disk);
+        }
+
+        let exporters = outputs
+            .into_iter()
+            .map(|output| {
+                Ok(match output {
+                    Output::Disk => {
+                        let exporter = self.disk.exporter(self.content_addressed)?;
+                        self.check_previews(&exporter.template)?;
+                        let base = exporter.directory.clone();
+                        self.boxed(exporter, self.manifest, Some(base), decks)
+                    }
+                    Output::S3 => {
+                        let exporter = self.s3.exporter(self.content_addressed)?;
+                        if let Some(template) = &exporter.template {
+                            self.check_previews(template)?;
                         }
-                    ))?
+                        self.boxed(exporter, self.manifest, None, decks)
+                    }
+                    Output::Http => {
+                        let exporter = self.http.exporter(self.content_addressed)?;
+                        self.check_previews(&exporter.template)?;
+                        self.boxed(exporter, self.manifest, None, decks)
+                    }
+                    Output::Serve => {
+                        let server =
+                            server.ok_or_else(|| eyre!("the serve output needs a server"))?;
+                        let exporter = self.disk.exporter(self.content_addressed)?;
+                        self.check_previews(&exporter.template)?;
+                        let directory = exporter.directory.clone();
+                        self.boxed(
+                            server.exporter(exporter, directory),
+                            self.manifest,
+                            None,
+                            decks,
+                        )
+                    }
+                    // the manifest is what tells collaborators which file is which
+                    Output::Zip => {
+                        let exporter = self.zip.exporter(self.content_addressed)?;
+                        self.boxed(exporter, true, Some(PathBuf::new()), decks)
+                    }
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(FanOut { exporters })
+    }
+
+    /// Makes sure previews don't overwrite the images they were made from.
+    fn check_previews(&self, template: &Template) -> Result<()> {
+        if !self.previews.is_empty() && !template.names_previews() {
+            Err(eyre!(
+                "the name template {template} would overwrite images with their previews, \
+                add {{preview}} or {{hash}} to it"
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Boxes `exporter`, wrapped in a [Manifest] and a [Gallery] if they should be written.
+    ///
+    /// Links in the gallery are relative to `base`, if the exported locations are below it.
+    fn boxed(
+        &self,
+        exporter: impl Store<Data = Vec<u8>, Output = PathBuf> + 'static,
+        manifest: bool,
+        base: Option<PathBuf>,
+        decks: &[Deck],
+    ) -> Exporter {
+        let exporter: Exporter = if manifest {
+            Box::new(Manifest::new(exporter))
+        } else {
+            Box::new(exporter)
+        };
+        if !self.gallery {
+            return exporter;
+        }
+
+        let mut gallery = Gallery::new(exporter);
+        gallery.base = base;
+        gallery.crop = self.gallery_crop;
+        for deck in decks {
+            gallery.describe(&deck.name, &deck.cards);
+        }
+        Box::new(gallery)
+    }
+}
+
+impl Png {
+    fn exporter(&self, background: Option<Color>) -> Result<PNGExporter> {
+        let mut exporter = PNGExporter::new(match background {
+            Some(background) => Alpha::Flatten(background),
+            None => Alpha::Keep,
+        });
+        exporter.compression = match self.png_compression {
+            PngCompression::Fast => CompressionLevel::Fast,
+            PngCompression::Default => CompressionLevel::Default,
+            PngCompression::High => CompressionLevel::High,
+        };
+        exporter.filter = match self.png_filter {
+            PngFilter::Adaptive => Mode::Adaptive,
+            PngFilter::None => Mode::Fixed(Filter::None),
+            PngFilter::Sub => Mode::Fixed(Filter::Sub),
+            PngFilter::Up => Mode::Fixed(Filter::Up),
+            PngFilter::Average => Mode::Fixed(Filter::Average),
+            PngFilter::Paeth => Mode::Fixed(Filter::Paeth),
+        };
+        exporter.strategy = match self.png_strategy {
+            PngStrategy::Adaptive => Mode::Adaptive,
+            PngStrategy::Default => Mode::Fixed(Strategy::Default),
+            PngStrategy::Filtered => Mode::Fixed(Strategy::Filtered),
+            PngStrategy::HuffmanOnly => Mode::Fixed(Strategy::HuffmanOnly),
+            PngStrategy::Rle => Mode::Fixed(Strategy::RLE),
+            PngStrategy::Fixed => Mode::Fixed(Strategy::Fixed),
+        };
+        exporter.metadata = !self.png_strip_metadata;
+        exporter.generator = format!("karten {}", env!("CARGO_PKG_VERSION"));
+
+        match self.png_threads {
+            Some(threads) => exporter
+                .with_threads(threads)
+                .map_err(|e| eyre!("couldn't start {threads} threads for encoding: {e}")),
+            None => Ok(exporter),
+        }
+    }
+}
+
+impl Disk {
+    fn exporter(&self, content_addressed: bool) -> Result<FileExporter> {
+        let directory = &self.directory;
+        if self.create || self.default_directory {
+            fs::create_dir_all(directory)?;
+        } else if !directory.is_dir() {
+            Err(eyre!(
+                "the directory {:?} doesn't exist {}. Use --create to create it",
+                directory,
+                if directory.is_relative() {
+                    format!("in {}", std::env::current_dir()?.display())
+                } else {
+                    String::new()
+                }
+            ))?
+        }
+
+        let directory = directory.canonicalize()?;
+
+        let template = if content_addressed {
+            Template::content_addressed()
+        } else {
+            self.name_template.clone()
+        };
+        let template = if self.subdirectories {
+            format!("{{deck}}/{template}")
+                .parse()
+                .map_err(|e: String| eyre!(e))?
+        } else {
+            template
+        };
+
+        let mut exporter = FileExporter::new(directory);
+        exporter.template = template;
+        exporter.prune = self.prune;
+
+        Ok(exporter)
+    }
+}
+
+impl Http {
+    fn exporter(&self, content_addressed: bool) -> Result<HttpExporter> {
+        let url = self
+            .http_url
+            .as_deref()
+            .ok_or_else(|| eyre!("the http output needs a URL to upload to"))
+            .suggestion("set --http-url or HTTP_URL")?;
+
+        let mut exporter = HttpExporter::new(url);
+        exporter.public_url = self.http_public_url.clone();
+        exporter.username = self.http_username.clone();
+        exporter.password = self.http_password.clone();
+        exporter.webdav = self.http_webdav;
+        exporter.template = if content_addressed {
+            Template::content_addressed()
+        } else {
+            self.http_name_template.clone()
+        };
+
+        Ok(exporter)
+    }
+}
+
+impl Zip {
+    fn exporter(&self, content_addressed: bool) -> Result<ZipExporter> {
+        let mut exporter = ZipExporter::new(self.zip_file.clone())
+            .with_context(|| format!("couldn't create {}", self.zip_file.display()))?;
+        if content_addressed {
+            exporter.template = Template::content_addressed();
+        }
+
+        Ok(exporter)
+    }
+}
+
+impl S3 {
+    fn profile_name(&self) -> &str {
+        self.s3_profile.as_deref().unwrap_or("default")
+    }
+
+    /// The profile set with --s3-profile, else the default one, which is read into `loaded` the first time.
+    fn profile<'a>(&self, loaded: &'a mut Option<Profile>) -> Result<&'a Profile> {
+        let profile = match loaded.take() {
+            Some(profile) => profile,
+            None => {
+                let profile = Profile::load(self.profile_name())?;
+                if self.s3_profile.is_some() && profile.is_none() {
+                    return Err(eyre!("there's no AWS profile {}", self.profile_name()))
+                        .suggestion(
+                            "check ~/.aws/credentials and ~/.aws/config or set --s3-profile",
+                        );
                 }
+                profile.unwrap_or_default()
+            }
+        };
+        Ok(loaded.insert(profile))
+    }
+
+    fn exporter(&self, content_addressed: bool) -> Result<S3Exporter> {
+        let s3_bucket = self
+            .s3_bucket
+            .as_deref()
+            .ok_or_else(|| eyre!("the s3 output needs a bucket"))
+            .suggestion("set --s3-bucket or S3_BUCKET")?;
+        // the profile is only read once a value falls back to it
+        let mut profile = None;
 
-                let directory = directory.canonicalize()?;
+        let s3_region = match self
+            .s3_region
+            .clone()
+            .or_else(|| env::var("AWS_REGION").ok())
+            .or_else(|| env::var("AWS_DEFAULT_REGION").ok())
+        {
+            Some(region) => region,
+            None => self
+                .profile(&mut profile)?
+                .region
+                .clone()
+                .ok_or_else(|| eyre!("the s3 output needs a region"))
+                .suggestion("set --s3-region or S3_REGION, or a region in the AWS profile")?,
+        };
+        let s3_endpoint = match self
+            .s3_endpoint
+            .clone()
+            .or_else(|| env::var("AWS_ENDPOINT_URL_S3").ok())
+            .or_else(|| env::var("AWS_ENDPOINT_URL").ok())
+        {
+            Some(endpoint) => Some(endpoint),
+            // most buckets don't need an endpoint, so a default profile that can't be read doesn't stop the run
+            None => match self.profile(&mut profile) {
+                Ok(profile) => profile.endpoint.clone(),
+                Err(e) if self.s3_profile.is_none() => {
+                    warn!("couldn't read the default AWS profile for an endpoint: {e}");
+                    None
+                }
+                Err(e) => return Err(e),
+            },
+        };
 
-                Ok(Box::new(FileExporter { directory }))
+        let credentials = if self.s3_profile.is_some() {
+            self.profile(&mut profile)?
+                .credentials()?
+                .ok_or_else(|| eyre!("the AWS profile {} has no access key", self.profile_name()))
+                .suggestion(
+                    "set aws_access_key_id and aws_secret_access_key in ~/.aws/credentials",
+                )?
+        } else {
+            match Credentials::from_env() {
+                Ok(credentials) => credentials,
+                Err(e) => self
+                    .profile(&mut profile)?
+                    .credentials()?
+                    .ok_or(e)
+                    .with_context(|| "couldn't build credentials from env vars")
+                    .suggestion(
+                        "set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or pick a profile with --s3-profile",
+                    )?,
             }
-            Output::S3 {
-                s3_bucket,
-                s3_region,
-                s3_endpoint,
-                s3_path_style,
-            } => {
-                let bucket = Bucket::new(
-                    &s3_bucket,
-                    if let Some(endpoint) = s3_endpoint {
-                        Region::Custom {
-                            region: s3_region,
-                            endpoint,
-                        }
+        };
+
+        let bucket = Bucket::new(
+            s3_bucket,
+            if let Some(endpoint) = s3_endpoint {
+                Region::Custom {
+                    region: s3_region,
+                    endpoint,
+                }
+            } else {
+                Region::from_str(&s3_region)
+                    .with_context(|| format!("couldn't parse a S3 Region from {s3_region}"))?
+            },
+            credentials,
+        )?;
+
+        let bucket = if self.s3_path_style {
+            bucket.with_path_style()
+        } else {
+            bucket
+        };
+
+        bucket
+            .location()
+            .with_context(|| format!("couldn't get bucket location: {}", bucket.host()))
+            .with_suggestion(|| format!("does {} exist in {}?", bucket.host(), bucket.region,))
+            .with_suggestion(|| {
+                format!(
+                    "the bucket is configured with {}. Maybe {} would work?",
+                    if bucket.is_path_style() {
+                        "path style"
+                    } else {
+                        "subdomain style"
+                    },
+                    if bucket.is_path_style() {
+                        "subdomain style"
                     } else {
-                        Region::from_str(&s3_region).with_context(|| {
-                            format!("couldn't parse a S3 Region from {s3_region}")
-                        })?
+                        "path style"
                     },
-                    Credentials::from_env()
-                        .with_context(|| "couldn't build credentials from env vars")?,
-                )?;
+                )
+            })
+            .with_suggestion(|| {
+                let credentials = bucket.credentials().unwrap();
 
-                let bucket = if s3_path_style {
-                    bucket.with_path_style()
-                } else {
-                    bucket
-                };
-
-                bucket
-                    .location()
-                    .with_context(|| format!("couldn't get bucket location: {}", bucket.host()))
-                    .with_suggestion(|| {
-                        format!("does {} exist in {}?", bucket.host(), bucket.region,)
-                    })
-                    .with_suggestion(|| {
-                        format!(
-                            "the bucket is configured with {}. Maybe {} would work?",
-                            if bucket.is_path_style() {
-                                "path style"
-                            } else {
-                                "subdomain style"
-                            },
-                            if bucket.is_path_style() {
-                                "subdomain style"
-                            } else {
-                                "path style"
-                            },
-                        )
-                    })
-                    .with_suggestion(|| {
-                        let credentials = bucket.credentials().unwrap();
-
-                        format!(
-                            "does {:?} have the permission `s3:GetBucketLocation`?",
-                            credentials
-                                .access_key
-                                .as_deref()
-                                .unwrap_or("[there's no access key?]")
-                        )
-                    })?;
+                format!(
+                    "does {:?} have the permission `s3:GetBucketLocation`?",
+                    credentials
+                        .access_key
+                        .as_deref()
+                        .unwrap_or("[there's no access key?]")
+                )
+            })?;
 
-                Ok(Box::new(S3Exporter::new(bucket)))
-            }
+        let mut exporter = S3Exporter::new(bucket);
+        exporter.template = if content_addressed {
+            Some(Template::content_addressed())
+        } else {
+            self.s3_name_template.clone()
+        };
+        exporter.prefix = self.s3_prefix.clone();
+        exporter.cache_control = self.s3_cache_control.clone();
+        exporter.acl = self.s3_acl.clone();
+        exporter.skip_unchanged = self.s3_skip_unchanged;
+        exporter.retries = self.s3_retries;
+        exporter.part_size = self.s3_part_size as usize * 1024 * 1024;
+        exporter.presign = self
+            .s3_presign_hours
+            .map(|hours| Duration::from_secs(hours * 60 * 60));
+        if self.s3_cleanup {
+            let retention = Duration::try_from_secs_f64(self.s3_retention_days * 24. * 60. * 60.)
+                .map_err(|e| eyre!("invalid --s3-retention-days: {e}"))?;
+            let mut cleanup = Cleanup::new(retention);
+            cleanup.dry_run = self.s3_cleanup_dry_run;
+            exporter.cleanup = Some(cleanup);
         }
+
+        if !self.s3_skip_preflight {
+            exporter
+                .preflight()
+                .map_err(|e| eyre!("the s3 preflight check failed: {e}"))
+                .suggestion("pass --s3-skip-preflight to upload anyway")?;
+        }
+
+        Ok(exporter)
     }
 }
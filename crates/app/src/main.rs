@@ -1,18 +1,21 @@
-use carp::{
-    artifact::Amount, dimensions::Dimensions, export::Export, renderer::ImageRenderer, tts::TTS,
-};
-use carp_export_png::PNGExporter;
-use clap::Parser;
-use color_eyre::Result;
+use carp::{artifact::Amount, metrics::Metrics, pipeline::Pipeline, renderer::ImageRenderer};
+use color_eyre::{eyre::eyre, Result};
 use dotenvy::dotenv;
-use log::info;
-use std::fs::{self};
+use log::{error, info};
+use std::{
+    fs::{self, File},
+    rc::Rc,
+};
 use tts_external_api::ExternalEditorApi;
 
+mod aws;
+mod catalog;
 mod cli;
 mod deck;
 mod draw;
 mod format;
+mod progress;
+mod serve;
 mod theme;
 mod tts;
 
@@ -33,16 +36,11 @@ fn main() -> Result<()> {
         eprintln!("Couldn\'t load a .env file with configuration info: {}", e);
     }
 
-    let args = cli::Args::parse();
-
-    // Configure pipeline
-    let exporter = args.output.unwrap_or_default().exporter()?;
-    let dimensions = Dimensions::new(args.resolution, args.aspect_ratio);
-    let renderer = ImageRenderer::new(dimensions);
-    let pngexporter = PNGExporter;
+    let args = cli::Args::parse_command_line();
 
-    // Run pipeline
-    args.input
+    // Load decks
+    let sources: Vec<_> = args
+        .input
         .iter()
         .filter_map(|source| {
             if source.is_dir() {
@@ -68,20 +66,65 @@ fn main() -> Result<()> {
             }
         })
         .flatten()
-        .map(fs::read_to_string)
-        .filter_map(Result::ok)
-        .map(|s| {
-            let deck = format::Deck::try_from(s.as_ref()).unwrap();
+        .filter_map(|path| match fs::read_to_string(&path) {
+            Ok(source) => Some((path, source)),
+            Err(e) => {
+                error!("couldn't read {}: {e}", path.display());
+                None
+            }
+        })
+        .collect();
+
+    let mut invalid = 0;
+    let decks: Vec<_> = sources
+        .iter()
+        .filter_map(
+            |(path, source)| match format::Deck::try_from(source.as_ref()) {
+                Ok(deck) => Some(deck),
+                Err(e) => {
+                    error!("couldn't load {}: {e}", path.display());
+                    invalid += 1;
+                    None
+                }
+            },
+        )
+        .collect();
+
+    if let Some(directory) = &args.catalog {
+        catalog::write(directory, &decks)?;
+    }
 
-            let deck = TTS::build(&deck, &renderer)
-                .map(Result::unwrap)
-                .map(|artifact| pngexporter.export(artifact).unwrap())
-                .map(|a| exporter.export(a).unwrap());
+    // Configure pipeline
+    let server = args.server()?;
+    // listen before anything points the Tabletop Simulator at the server
+    let serving = match &server {
+        Some(server) => {
+            let serving = server.spawn()?;
+            info!("Serving the decks at {}", server.url);
+            Some(serving)
+        }
+        None => None,
+    };
+    let exporter = args.exporter(&decks, server.as_ref())?;
+    let dimensions = args.dimensions();
+    let metrics = Rc::new(Metrics::new());
+    let pipeline = Pipeline::new(
+        args.layout,
+        ImageRenderer::new(dimensions),
+        args.encoder()?,
+        exporter,
+    )
+    .with_previews(args.previews.iter().copied())
+    .observe(progress::Progress::new())
+    .observe(metrics.clone());
 
-            let mut deck: Vec<_> = deck.collect();
+    // Run pipeline
+    let mut report = pipeline.run(&decks);
 
-            // Make sure backs and fronts are next to one another
-            deck.sort_unstable_by(|a, b| match (a.amount, b.amount) {
+    for (index, deck) in report.decks.iter_mut().enumerate() {
+        // Make sure backs and fronts are next to one another
+        deck.artifacts
+            .sort_unstable_by(|a, b| match (a.amount, b.amount) {
                 (Amount::Single, Amount::Single) => std::cmp::Ordering::Equal,
                 (Amount::Single, Amount::Multiple { .. }) => std::cmp::Ordering::Less,
                 (Amount::Multiple { .. }, Amount::Single) => std::cmp::Ordering::Greater,
@@ -89,17 +132,49 @@ fn main() -> Result<()> {
                     ia.cmp(&ib)
                 }
             });
-            deck
-        })
-        .enumerate()
-        .for_each(|(index, deck)| {
-            if args.sync_to_tts {
-                let api = ExternalEditorApi::new();
-                tts::spawn_deck(&api, &deck, (index as f32 * 2.4, 0.0, 0.0)).unwrap();
+
+        if args.sync_to_tts {
+            let api = ExternalEditorApi::new();
+            let position = (index as f32 * 2.4, 0.0, 0.0);
+            if let Err(e) = tts::spawn_deck(&api, &deck.artifacts, position) {
+                error!("couldn't sync {} to the Tabletop Simulator: {e}", deck.deck);
             }
-        });
+        }
+    }
+
+    // a failure here doesn't stop the server, the decks might have been synced to it already
+    let metrics = match args.metrics {
+        Some(path) => File::create(&path)
+            .map_err(Into::into)
+            .and_then(|file| metrics.write_json(file))
+            .map_err(|e| eyre!("couldn't write metrics to {}: {e}", path.display())),
+        None => Ok(()),
+    };
+    if let (Err(e), Some(_)) = (&metrics, &serving) {
+        // the error is only returned once the server stops
+        error!("{e}");
+    }
+
+    if report.is_success() {
+        info!("{report}");
+    } else {
+        error!("{report}");
+    }
 
     info!("Done in {:.2?}", start.elapsed());
 
-    Ok(())
+    if let Some(serving) = serving {
+        info!("Still serving the decks, stop with Ctrl+C");
+        let _ = serving.join();
+    }
+
+    if report.is_success() && invalid == 0 {
+        metrics
+    } else {
+        Err(eyre!(
+            "{} decks couldn't be loaded and the run had {} failures",
+            invalid,
+            report.failures().count()
+        ))
+    }
 }
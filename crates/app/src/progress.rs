@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use carp::event::{Event, Observer};
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Shows how many decks of a run are done and what is being worked on right now.
+pub struct Progress {
+    bar: ProgressBar,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        let bar = ProgressBar::new(0).with_style(
+            ProgressStyle::with_template(
+                "{spinner} [{elapsed_precise}] {bar:32} {pos}/{len} decks {wide_msg}",
+            )
+            .expect("the template is valid"),
+        );
+        bar.enable_steady_tick(Duration::from_millis(120));
+        Self { bar }
+    }
+}
+
+impl Observer for Progress {
+    fn notify(&self, event: &Event<'_>) {
+        match event {
+            Event::RunStarted { decks } => self.bar.set_length(*decks as u64),
+            Event::DeckStarted { deck, .. } => self.bar.set_message(format!("{deck}: rendering")),
+            Event::SheetRendered { artifact, took, .. } => self
+                .bar
+                .set_message(format!("{artifact}: rendered in {took:.2?}, encoding")),
+            Event::PreviewScaled { artifact, took } => self
+                .bar
+                .set_message(format!("{artifact}: scaled in {took:.2?}, encoding")),
+            Event::ArtifactEncoded { artifact, took, .. } => self
+                .bar
+                .set_message(format!("{artifact}: encoded in {took:.2?}, exporting")),
+            Event::ArtifactExported { artifact, took } => self
+                .bar
+                .set_message(format!("{artifact}: exported in {took:.2?}")),
+            Event::Failed { failure, .. } => self.bar.set_message(failure.to_string()),
+            Event::DeckFinished { .. } => self.bar.inc(1),
+            Event::RunFinished { .. } => self.bar.finish_and_clear(),
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Serves the export directory over HTTP, so the Tabletop Simulator of every player in the LAN can load the images.
+//!
+//! This is a minimal server for GET and HEAD requests, it's not meant to face the internet.
+
+use carp::{
+    artifact::Artifact,
+    export::{content_type, encode, Export, Store},
+};
+use log::{debug, warn};
+use std::{
+    error::Error,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::{Component, Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Listens for requests for the files in a directory.
+pub struct Server {
+    listener: TcpListener,
+    directory: PathBuf,
+    /// The URL the directory is served at, ending in a slash.
+    pub url: String,
+}
+
+impl Server {
+    /// Listens on `address` for requests for the files in `directory`.
+    ///
+    /// The URLs name `host`, or else the address other machines in the LAN reach this one at.
+    pub fn bind(address: SocketAddr, host: Option<&str>, directory: PathBuf) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        // the port is picked by the system if it's 0
+        let port = listener.local_addr()?.port();
+        let url = match host {
+            Some(host) => format!("http://{host}:{port}/"),
+            None => {
+                let ip = if address.ip().is_unspecified() {
+                    lan_address().unwrap_or_else(|| {
+                        warn!("couldn't find the address of this machine in the LAN, only this machine can load the images");
+                        IpAddr::V4(Ipv4Addr::LOCALHOST)
+                    })
+                } else {
+                    address.ip()
+                };
+                format!("http://{}/", SocketAddr::new(ip, port))
+            }
+        };
+
+        Ok(Self {
+            listener,
+            directory,
+            url,
+        })
+    }
+
+    /// Wraps `exporter` so it outputs the URLs of the files it writes below `directory`.
+    pub fn exporter<X>(&self, exporter: X, directory: PathBuf) -> Served<X> {
+        Served {
+            exporter,
+            directory,
+            url: self.url.clone(),
+        }
+    }
+
+    /// Answers requests on a background thread, which runs until the process is stopped.
+    pub fn spawn(&self) -> io::Result<JoinHandle<()>> {
+        let listener = self.listener.try_clone()?;
+        let directory = self.directory.clone();
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let directory = directory.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = respond(&directory, stream) {
+                                debug!("couldn't answer a request: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => warn!("couldn't accept a connection: {e}"),
+                }
+            }
+        }))
+    }
+}
+
+/// The address of the interface that routes to other machines.
+fn lan_address() -> Option<IpAddr> {
+    // connecting a UDP socket doesn't send anything, but picks the interface
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified()).then_some(ip)
+}
+
+/// Answers a single request on `stream` with a file from `directory`.
+fn respond(directory: &Path, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new((&stream).take(16 * 1024));
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // the headers don't matter, but have to be read before answering
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return reply(
+            &stream,
+            "400 Bad Request",
+            "text/plain",
+            b"bad request",
+            true,
+        );
+    };
+    if method != "GET" && method != "HEAD" {
+        return reply(
+            &stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"only GET and HEAD are allowed",
+            true,
+        );
+    }
+    let body = method == "GET";
+
+    let Some(path) = resolve(directory, target) else {
+        return reply(&stream, "404 Not Found", "text/plain", b"not found", body);
+    };
+    let path = if path.is_dir() {
+        path.join("index.html")
+    } else {
+        path
+    };
+    match fs::read(&path) {
+        Ok(data) => {
+            debug!("serving {}", path.display());
+            let extension = path.extension().unwrap_or_default();
+            reply(
+                &stream,
+                "200 OK",
+                content_type(&extension.to_string_lossy()),
+                &data,
+                body,
+            )
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            reply(&stream, "404 Not Found", "text/plain", b"not found", body)
+        }
+        Err(e) => {
+            warn!("couldn't read {}: {e}", path.display());
+            reply(
+                &stream,
+                "500 Internal Server Error",
+                "text/plain",
+                b"couldn't read the file",
+                body,
+            )
+        }
+    }
+}
+
+/// Writes a response with `data`, which is only sent with `body`, so HEAD requests get the headers alone.
+fn reply(
+    mut stream: &TcpStream,
+    status: &str,
+    content_type: &str,
+    data: &[u8],
+    body: bool,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: {content_type}\r\n\
+        Content-Length: {}\r\n\
+        Cache-Control: no-cache\r\n\
+        Access-Control-Allow-Origin: *\r\n\
+        Connection: close\r\n\r\n",
+        data.len()
+    )?;
+    if body {
+        stream.write_all(data)?;
+    }
+    stream.flush()
+}
+
+/// The file in `directory` that the request `target` asks for, unless it points outside of `directory`.
+fn resolve(directory: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next()?.strip_prefix('/')?;
+    let path = decode(path)?;
+    let mut resolved = directory.to_path_buf();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        // only plain names, no `..`, drive letters or backslashes that are separators on Windows
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if !segment.contains('\\') => resolved.push(name),
+            _ => return None,
+        }
+    }
+    Some(resolved)
+}
+
+/// Decodes the `%XX` escapes in `path`.
+fn decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Outputs the URLs a [Server] serves the files at, instead of their paths.
+pub struct Served<X> {
+    pub exporter: X,
+    /// The directory the [Server] serves.
+    pub directory: PathBuf,
+    /// The URL the directory is served at.
+    pub url: String,
+}
+
+impl<X> Served<X> {
+    /// The URL of `path`, if it's below [`Served::directory`].
+    fn locate(&self, path: PathBuf) -> PathBuf {
+        match path.strip_prefix(&self.directory) {
+            Ok(relative) => {
+                let relative: Vec<_> = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect();
+                format!("{}{}", self.url, encode(&relative.join("/"))).into()
+            }
+            Err(_) => path,
+        }
+    }
+}
+
+impl<X> Export for Served<X>
+where
+    X: Export<Output = PathBuf>,
+{
+    type Data = X::Data;
+    type Output = PathBuf;
+
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        let artifact = self.exporter.export(artifact)?;
+        let location = self.locate(artifact.data.clone());
+        Ok(artifact.with_data(location))
+    }
+
+    fn finish_deck(&self, deck: &str) -> Result<(), Box<dyn Error>> {
+        self.exporter.finish_deck(deck)
+    }
+
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        self.exporter.finish(complete)
+    }
+}
+
+impl<X> Store for Served<X>
+where
+    X: Store<Output = PathBuf>,
+{
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        Ok(self.locate(self.exporter.store(name, data)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolving() {
+        let directory = Path::new("export");
+        assert_eq!(
+            resolve(directory, "/Base%20Game/front.png?v=2"),
+            Some(directory.join("Base Game").join("front.png"))
+        );
+        assert_eq!(resolve(directory, "/"), Some(directory.to_path_buf()));
+        assert_eq!(resolve(directory, "/../secret"), None);
+        assert_eq!(resolve(directory, "/%2e%2e/secret"), None);
+        assert_eq!(resolve(directory, "/a%5c..%5csecret"), None);
+        assert_eq!(resolve(directory, "/%zz"), None);
+        assert_eq!(resolve(directory, "http://example.com/"), None);
+    }
+
+    #[test]
+    fn locating() {
+        let served = Served {
+            exporter: (),
+            directory: PathBuf::from("export"),
+            url: "http://192.168.0.2:8080/".into(),
+        };
+        assert_eq!(
+            served.locate(Path::new("export").join("Base Game").join("front.png")),
+            PathBuf::from("http://192.168.0.2:8080/Base%20Game/front.png")
+        );
+        assert_eq!(
+            served.locate("elsewhere.png".into()),
+            PathBuf::from("elsewhere.png")
+        );
+    }
+}
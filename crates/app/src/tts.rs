@@ -2,33 +2,52 @@ use carp::{
     artifact::{Artifact, Content},
     Side,
 };
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Help, Result};
 use std::path::{Path, PathBuf};
 use tts_external_api::ExternalEditorApi;
 
+/// Picks the location the Tabletop Simulator can load an artifact from,
+/// preferring URLs over files on this machine.
+///
+/// [None] if it can't load any of them, e.g. if the artifact only went into a zip archive.
+fn reachable(locations: &[PathBuf]) -> Option<&Path> {
+    locations
+        .iter()
+        .find(|location| location.to_string_lossy().starts_with("http"))
+        .or_else(|| locations.iter().find(|location| location.is_file()))
+        .map(PathBuf::as_path)
+}
+
 pub fn spawn_deck(
     api: &ExternalEditorApi,
-    deck: &[Artifact<PathBuf>],
+    deck: &[Artifact<Vec<PathBuf>>],
     position: (f32, f32, f32),
 ) -> Result<()> {
     if deck.is_empty() {
         return Ok(());
     }
 
+    // previews are too small to play with
     let backs = deck
         .iter()
-        .filter(|artifact| artifact.side == Side::Back)
+        .filter(|artifact| artifact.side == Side::Back && artifact.preview.is_none())
         .cycle();
 
     for (front, back) in deck
         .iter()
-        .filter(|artifact| artifact.side == Side::Front)
+        .filter(|artifact| artifact.side == Side::Front && artifact.preview.is_none())
         .zip(backs)
     {
+        let (Some(face), Some(back_face)) = (reachable(&front.data), reachable(&back.data)) else {
+            return Err(eyre!(
+                "the Tabletop Simulator can't load {front} from any of the outputs"
+            ))
+            .suggestion("add the disk output or one that uploads the images");
+        };
         let _ = api.execute(spawn_card_or_deck_tts(
             position,
-            &front.data,
-            &back.data,
+            face,
+            back_face,
             front.content,
             front.aspect_ratio.map_or(false, |a| a.is_landscape()),
             true,
@@ -97,3 +116,20 @@ end
         ),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reachable_locations() {
+        let url = PathBuf::from("http://192.168.0.2:8080/deck-front.png");
+        let archived = PathBuf::from("export.zip/deck-front.png");
+        assert_eq!(
+            reachable(&[archived.clone(), url.clone()]),
+            Some(url.as_path())
+        );
+        assert_eq!(reachable(&[archived]), None);
+        assert_eq!(reachable(&[]), None);
+    }
+}
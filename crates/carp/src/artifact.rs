@@ -1,7 +1,9 @@
 use crate::{card::Side, dimensions::AspectRatio, Backside};
+use serde::Serialize;
 use std::fmt::{Display, Formatter};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Content {
     Single,
     Sheet { rows: u16, columns: u16, total: u16 },
@@ -20,7 +22,8 @@ impl Display for Content {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Amount {
     Single,
     Multiple { index: u16, total: u16 },
@@ -46,6 +49,8 @@ pub struct Artifact<Format> {
     pub aspect_ratio: Option<AspectRatio>,
     /// The file extension commonly associated with [Format].
     pub extension: Option<String>,
+    /// If this is a downscaled copy of another artifact, the length of its longer side in pixels.
+    pub preview: Option<u32>,
 }
 
 impl<Format> Display for Artifact<Format> {
@@ -54,7 +59,11 @@ impl<Format> Display for Artifact<Format> {
             f,
             "{}-{}-{}-{}",
             self.deck, self.side, self.content, self.amount
-        )
+        )?;
+        if let Some(preview) = self.preview {
+            write!(f, "-{preview}px")?;
+        }
+        Ok(())
     }
 }
 
@@ -69,6 +78,23 @@ impl<Format> Artifact<Format> {
             deck: self.deck,
             aspect_ratio: self.aspect_ratio,
             extension: self.extension,
+            preview: self.preview,
+        }
+    }
+
+    /// Copies everything but the data.
+    #[must_use]
+    pub fn meta(&self) -> Artifact<()> {
+        Artifact {
+            data: (),
+            amount: self.amount,
+            content: self.content,
+            side: self.side,
+            shared: self.shared,
+            deck: self.deck.clone(),
+            aspect_ratio: self.aspect_ratio,
+            extension: self.extension.clone(),
+            preview: self.preview,
         }
     }
 
@@ -84,6 +110,7 @@ impl<Format> Artifact<Format> {
                 deck: self.deck,
                 aspect_ratio: self.aspect_ratio,
                 extension: self.extension,
+                preview: self.preview,
             },
         )
     }
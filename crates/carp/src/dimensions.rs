@@ -1,10 +1,11 @@
 use std::str::FromStr;
 
 use piet_common::kurbo::Size;
+use serde::Serialize;
 
 use crate::{BASE_ASPECT_RATIO, BASE_RESOLUTION, COLUMNS, ROWS};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
 pub struct AspectRatio(pub f64);
 
 impl AspectRatio {
@@ -73,6 +74,8 @@ pub struct Dimensions {
     pub width: u32,
     pub card: Size,
     pub pix_scale: f64,
+    /// The margin around a single card that gets cut off when printing, in the same units as `card`.
+    pub bleed: f64,
 }
 
 impl Dimensions {
@@ -114,6 +117,52 @@ impl Dimensions {
             width: deck_width,
             card: Size::new(card_width, card_height),
             pix_scale,
+            bleed: 0.,
         }
     }
+
+    /// Dimensions for printing single cards that are `card_width` inches wide at `dpi`,
+    /// with `bleed` inches of extra image around every side.
+    ///
+    /// Cards are drawn at the same size as for sheets, just with a different scale,
+    /// so they look the same either way.
+    #[must_use]
+    pub fn print(
+        card_aspect_ratio: AspectRatio,
+        dpi: u32,
+        card_width: f64,
+        bleed: f64,
+    ) -> Self {
+        let card = Self::new(BASE_RESOLUTION, card_aspect_ratio).card;
+        let pix_scale = f64::from(dpi) * card_width / card.width;
+        let bleed = f64::from(dpi) * bleed / pix_scale;
+
+        Self {
+            height: ((card.height + 2. * bleed) * pix_scale).round() as u32,
+            width: ((card.width + 2. * bleed) * pix_scale).round() as u32,
+            card,
+            pix_scale,
+            bleed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn print() {
+        // 2 by 4 inches at 300 dpi, with an eighth of an inch of bleed
+        let dimensions = Dimensions::print(AspectRatio(0.5), 300, 2., 0.125);
+        assert_eq!((dimensions.width, dimensions.height), (675, 1275));
+        assert_eq!(dimensions.card.width * dimensions.pix_scale, 600.);
+        assert_eq!(dimensions.bleed * dimensions.pix_scale, 37.5);
+        // drawn at the same size as on a sheet
+        assert_eq!(dimensions.card, Dimensions::new(BASE_RESOLUTION, AspectRatio(0.5)).card);
+
+        let dimensions = Dimensions::print(BASE_ASPECT_RATIO, 600, 2.5, 0.);
+        assert_eq!(dimensions.width, 1500);
+        assert_eq!(dimensions.bleed, 0.);
+    }
 }
@@ -0,0 +1,75 @@
+use std::{rc::Rc, sync::Arc, time::Duration};
+
+use crate::{artifact::Artifact, pipeline::Failure, renderer::Timings};
+
+/// Something that happened while a [Pipeline](crate::pipeline::Pipeline) was running.
+///
+/// Events refer to artifacts without their data, see [`Artifact::meta`].
+#[derive(Debug)]
+pub enum Event<'a> {
+    RunStarted {
+        decks: usize,
+    },
+    DeckStarted {
+        deck: &'a str,
+        /// The position of the deck in the run, starting at 0.
+        index: usize,
+    },
+    SheetRendered {
+        artifact: &'a Artifact<()>,
+        took: Duration,
+        /// The breakdown of `took`, if the renderer measures it.
+        timings: Option<Timings>,
+    },
+    /// A smaller copy of a rendered sheet was made, see [`Pipeline::with_previews`](crate::pipeline::Pipeline::with_previews).
+    PreviewScaled {
+        artifact: &'a Artifact<()>,
+        took: Duration,
+    },
+    ArtifactEncoded {
+        artifact: &'a Artifact<()>,
+        took: Duration,
+        /// The size of the encoded data.
+        bytes: usize,
+    },
+    ArtifactExported {
+        artifact: &'a Artifact<()>,
+        took: Duration,
+    },
+    Failed {
+        failure: &'a Failure,
+        took: Duration,
+    },
+    DeckFinished {
+        deck: &'a str,
+        took: Duration,
+    },
+    RunFinished {
+        took: Duration,
+    },
+}
+
+/// Gets notified about every [Event] of a [Pipeline](crate::pipeline::Pipeline).
+///
+/// Closures taking an `&Event` are observers too.
+pub trait Observer {
+    fn notify(&self, event: &Event<'_>);
+}
+
+impl<F: Fn(&Event<'_>)> Observer for F {
+    fn notify(&self, event: &Event<'_>) {
+        self(event);
+    }
+}
+
+impl<T: Observer + ?Sized> Observer for Rc<T> {
+    fn notify(&self, event: &Event<'_>) {
+        (**self).notify(event);
+    }
+}
+
+impl<T: Observer + ?Sized> Observer for Arc<T> {
+    fn notify(&self, event: &Event<'_>) {
+        (**self).notify(event);
+    }
+}
@@ -1,5 +1,54 @@
 use crate::artifact::Artifact;
-use std::{error::Error, fs::File, io::Write, path::PathBuf};
+use log::{debug, info};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+mod gallery;
+mod manifest;
+mod template;
+
+pub use gallery::Gallery;
+pub use manifest::{Entry, Manifest};
+use template::relative_path;
+pub use template::{sanitize, Template};
+
+/// The media type of files with `extension`, for exporters that serve their files over HTTP.
+#[must_use]
+pub fn content_type(extension: &str) -> &'static str {
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "json" => "application/json",
+        "html" | "htm" => "text/html; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Percent-encodes the segments of the relative URL `path`, keeping the slashes between them.
+#[must_use]
+pub fn encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(char::from(byte));
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
 
 pub trait Export {
     type Data;
@@ -8,13 +57,264 @@ pub trait Export {
         &self,
         artifact: Artifact<Self::Data>,
     ) -> Result<Artifact<Self::Output>, Box<dyn Error>>;
+
+    /// Called once every artifact of `deck` went through [`Export::export`].
+    fn finish_deck(&self, deck: &str) -> Result<(), Box<dyn Error>> {
+        let _ = deck;
+        Ok(())
+    }
+
+    /// Called once at the end of a run,
+    /// e.g. to write an index, flush batched uploads or remove stale files.
+    ///
+    /// `complete` is false if anything in the run failed.
+    /// Stale files might be the last good copy of what failed then, so they have to stay.
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        let _ = complete;
+        Ok(())
+    }
+
+    /// Feeds the output of this exporter into `next`.
+    fn then<Next>(self, next: Next) -> Chain<Self, Next>
+    where
+        Self: Sized,
+        Next: Export<Data = Self::Output>,
+    {
+        Chain {
+            first: self,
+            second: next,
+        }
+    }
+}
+
+impl<T: Export + ?Sized> Export for Box<T> {
+    type Data = T::Data;
+    type Output = T::Output;
+
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        (**self).export(artifact)
+    }
+
+    fn finish_deck(&self, deck: &str) -> Result<(), Box<dyn Error>> {
+        (**self).finish_deck(deck)
+    }
+
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        (**self).finish(complete)
+    }
+}
+
+/// An exporter that can also put files of its own next to the artifacts, like an index or a manifest.
+pub trait Store: Export {
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>>;
+}
+
+impl<T: Store + ?Sized> Store for Box<T> {
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        (**self).store(name, data)
+    }
+}
+
+/// Two exporters run one after another, see [`Export::then`].
+pub struct Chain<First, Second> {
+    pub first: First,
+    pub second: Second,
+}
+
+impl<First, Second> Export for Chain<First, Second>
+where
+    First: Export,
+    Second: Export<Data = First::Output>,
+{
+    type Data = First::Data;
+    type Output = Second::Output;
+
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        self.second.export(self.first.export(artifact)?)
+    }
+
+    fn finish_deck(&self, deck: &str) -> Result<(), Box<dyn Error>> {
+        let first = self.first.finish_deck(deck);
+        self.second.finish_deck(deck)?;
+        first
+    }
+
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        let first = self.first.finish(complete);
+        self.second.finish(complete && first.is_ok())?;
+        first
+    }
+}
+
+/// Hands every artifact to several exporters and collects all of their outputs,
+/// e.g. to keep a local copy of everything that gets uploaded.
+pub struct FanOut<X> {
+    pub exporters: Vec<X>,
+}
+
+impl<X> FanOut<X> {
+    /// Runs `f` for every exporter, even if some of them fail. Returns the first error.
+    fn each<T>(
+        &self,
+        mut f: impl FnMut(&X) -> Result<T, Box<dyn Error>>,
+    ) -> Result<Vec<T>, Box<dyn Error>> {
+        let mut outputs = Vec::with_capacity(self.exporters.len());
+        let mut error = None;
+        for exporter in &self.exporters {
+            match f(exporter) {
+                Ok(output) => outputs.push(output),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        match error {
+            Some(error) => Err(error),
+            None => Ok(outputs),
+        }
+    }
+}
+
+impl<X> Export for FanOut<X>
+where
+    X: Export,
+    X::Data: Clone,
+{
+    type Data = X::Data;
+    type Output = Vec<X::Output>;
+
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        let (data, artifact) = artifact.extract_data();
+        let exported =
+            self.each(|exporter| exporter.export(artifact.clone().with_data(data.clone())))?;
+
+        // The metadata is taken from the first exporter, as they might change it.
+        let mut first = None;
+        let mut outputs = Vec::with_capacity(exported.len());
+        for exported in exported {
+            let (output, exported) = exported.extract_data();
+            outputs.push(output);
+            first.get_or_insert(exported);
+        }
+        Ok(first.unwrap_or(artifact).with_data(outputs))
+    }
+
+    fn finish_deck(&self, deck: &str) -> Result<(), Box<dyn Error>> {
+        self.each(|exporter| exporter.finish_deck(deck)).map(drop)
+    }
+
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        self.each(|exporter| exporter.finish(complete)).map(drop)
+    }
+}
+
+impl<X> Store for FanOut<X>
+where
+    X: Store,
+    X::Data: Clone,
+{
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        self.each(|exporter| exporter.store(name, data))
+    }
 }
 
 /// An exporter that writes files to disk.
 /// It takes Bytes and writes them as files in the given directory.
+///
+/// Files are written to a temporary file first and then renamed,
+/// so an interrupted run never leaves half written files behind.
 pub struct FileExporter {
     /// The directory in which files will be placed.
     pub directory: PathBuf,
+    /// How the files are named, relative to [`FileExporter::directory`].
+    pub template: Template,
+    /// Whether to remove the files from the directory that are named like [`FileExporter::template`] names them,
+    /// but weren't written during this run, once the run is finished without failures.
+    pub prune: bool,
+    /// The paths written in this run, relative to [`FileExporter::directory`].
+    written: Mutex<HashSet<PathBuf>>,
+}
+
+impl FileExporter {
+    #[must_use]
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            template: Template::default(),
+            prune: false,
+            written: Mutex::default(),
+        }
+    }
+
+    /// Writes `data` to `relative` in [`FileExporter::directory`] and returns the full path.
+    fn write(&self, relative: PathBuf, data: &[u8]) -> Result<PathBuf, Box<dyn Error>> {
+        let path = self.directory.join(&relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut temporary = path.to_path_buf().into_os_string();
+        temporary.push(format!(".{}.tmp", std::process::id()));
+        let temporary = PathBuf::from(temporary);
+
+        let written = File::create(&temporary)
+            .and_then(|mut writer| {
+                writer.write_all(data)?;
+                writer.sync_all()
+            })
+            .and_then(|()| fs::rename(&temporary, &path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temporary);
+            return Err(e.into());
+        }
+
+        self.written
+            .lock()
+            .map_err(|e| e.to_string())?
+            .insert(relative);
+        Ok(path)
+    }
+
+    /// Removes the files below `relative` in [`FileExporter::directory`] that [`FileExporter::template`] could have named,
+    /// but that weren't written in this run, and the directories that this empties.
+    /// Returns how many entries of `relative` were kept and how many were removed.
+    fn prune_directory(
+        &self,
+        relative: &Path,
+        written: &HashSet<PathBuf>,
+    ) -> Result<(usize, usize), Box<dyn Error>> {
+        let (mut kept, mut removed) = (0, 0);
+        for entry in fs::read_dir(self.directory.join(relative))? {
+            let entry = entry?;
+            let (path, relative) = (entry.path(), relative.join(entry.file_name()));
+            if entry.file_type()?.is_dir() {
+                match self.prune_directory(&relative, written)? {
+                    (0, 1..) => {
+                        debug!("Removing empty directory {}", path.display());
+                        fs::remove_dir(&path)?;
+                        removed += 1;
+                    }
+                    _ => kept += 1,
+                }
+            } else if written.contains(&relative) || !self.template.matches(&name(&relative)) {
+                kept += 1;
+            } else {
+                info!("Removing stale file {}", path.display());
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok((kept, removed))
+    }
 }
 
 impl Export for FileExporter {
@@ -25,19 +325,259 @@ impl Export for FileExporter {
         &self,
         artifact: Artifact<Self::Data>,
     ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
-        let mut path = self.directory.join(artifact.to_string());
-
-        if let Some(ref fileformat) = artifact.extension {
-            path = path.with_extension(fileformat);
-        }
-
-        let mut writer = File::create(&path)?;
-
-        writer.write_all(&artifact.data)?;
+        let path = self.write(self.template.path(&artifact), &artifact.data)?;
 
         Ok(Artifact {
             aspect_ratio: artifact.aspect_ratio,
             ..artifact.with_data(path)
         })
     }
+
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        if self.prune && !complete {
+            info!("Not removing stale files, as the run didn't complete");
+        } else if self.prune {
+            let written = self.written.lock().map_err(|e| e.to_string())?;
+            self.prune_directory(Path::new(""), &written)?;
+        }
+        Ok(())
+    }
+}
+
+impl Store for FileExporter {
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        self.write(relative_path(name), data)
+    }
+}
+
+/// `relative` with `/` between its components, the way a [Template] names it.
+fn name(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        artifact::{Amount, Content},
+        Backside, Side,
+    };
+
+    /// Records what it was asked to do and fails at everything if `fail` is set.
+    struct Recorder {
+        name: &'static str,
+        fail: bool,
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl Recorder {
+        fn new(name: &'static str, fail: bool) -> Self {
+            Self {
+                name,
+                fail,
+                calls: RefCell::default(),
+            }
+        }
+
+        fn record(&self, call: String) -> Result<(), Box<dyn Error>> {
+            self.calls.borrow_mut().push(call);
+            match self.fail {
+                true => Err(format!("{} failed", self.name).into()),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl Export for Recorder {
+        type Data = Vec<u8>;
+        type Output = String;
+
+        fn export(
+            &self,
+            artifact: Artifact<Self::Data>,
+        ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+            self.record(format!("export {artifact}"))?;
+            let (data, mut artifact) = artifact.extract_data();
+            // exporters may change the metadata, e.g. the extension of the format they encode to
+            artifact.extension = Some(self.name.into());
+            Ok(artifact.with_data(format!("{}: {} bytes", self.name, data.len())))
+        }
+
+        fn finish_deck(&self, deck: &str) -> Result<(), Box<dyn Error>> {
+            self.record(format!("finish {deck}"))
+        }
+
+        fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+            self.record(format!("finish complete: {complete}"))
+        }
+    }
+
+    #[test]
+    fn fanning_out() {
+        let fan_out = FanOut {
+            exporters: vec![Recorder::new("a", false), Recorder::new("b", false)],
+        };
+        let exported = fan_out.export(artifact("deck")).unwrap();
+        assert_eq!(exported.data, ["a: 5 bytes", "b: 5 bytes"]);
+        assert_eq!(exported.extension.as_deref(), Some("a"));
+
+        fan_out.finish_deck("deck").unwrap();
+        fan_out.finish(true).unwrap();
+        for exporter in &fan_out.exporters {
+            assert_eq!(
+                *exporter.calls.borrow(),
+                [
+                    "export deck-front-single-1of1",
+                    "finish deck",
+                    "finish complete: true"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn fanning_out_failures() {
+        let fan_out = FanOut {
+            exporters: vec![
+                Recorder::new("a", false),
+                Recorder::new("b", true),
+                Recorder::new("c", true),
+            ],
+        };
+        // the first error, after every exporter had its turn
+        let error = fan_out.export(artifact("deck")).unwrap_err();
+        assert_eq!(error.to_string(), "b failed");
+        assert_eq!(
+            fan_out.finish_deck("deck").unwrap_err().to_string(),
+            "b failed"
+        );
+        assert_eq!(fan_out.finish(false).unwrap_err().to_string(), "b failed");
+        for exporter in &fan_out.exporters {
+            assert_eq!(
+                *exporter.calls.borrow(),
+                [
+                    "export deck-front-single-1of1",
+                    "finish deck",
+                    "finish complete: false"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode("Base Game/front-1.png"), "Base%20Game/front-1.png");
+        assert_eq!(encode("my deck #1.png"), "my%20deck%20%231.png");
+        assert_eq!(encode("Größe?#.png"), "Gr%C3%B6%C3%9Fe%3F%23.png");
+    }
+
+    fn artifact(deck: &str) -> Artifact<Vec<u8>> {
+        Artifact {
+            deck: deck.into(),
+            shared: Backside::Shared,
+            data: b"image".to_vec(),
+            side: Side::Front,
+            content: Content::Single,
+            amount: Amount::Single,
+            aspect_ratio: None,
+            extension: Some("png".into()),
+            preview: None,
+        }
+    }
+
+    /// The files below `directory`, with `/` between directories.
+    fn files(directory: &Path) -> Vec<String> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            let name = name(path.strip_prefix(directory).unwrap());
+            if path.is_dir() {
+                files.extend(files_in(&path, &name));
+            } else {
+                files.push(name);
+            }
+        }
+        files.sort();
+        files
+    }
+
+    fn files_in(directory: &Path, prefix: &str) -> Vec<String> {
+        let mut files: Vec<_> = files(directory)
+            .into_iter()
+            .map(|file| format!("{prefix}/{file}"))
+            .collect();
+        if files.is_empty() {
+            files.push(format!("{prefix}/"));
+        }
+        files
+    }
+
+    #[test]
+    fn writing() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut exporter = FileExporter::new(directory.path().into());
+        exporter.template = "{deck}/{side}.{ext}".parse().unwrap();
+
+        let exported = exporter.export(artifact("deck")).unwrap();
+        assert_eq!(
+            exported.data,
+            directory.path().join("deck").join("front.png")
+        );
+        assert_eq!(fs::read(&exported.data).unwrap(), b"image");
+
+        let mut replaced = artifact("deck");
+        replaced.data = b"changed".to_vec();
+        exporter.export(replaced).unwrap();
+        exporter.store("manifest.json", b"{}").unwrap();
+        assert_eq!(fs::read(&exported.data).unwrap(), b"changed");
+
+        // the temporary files were renamed
+        assert_eq!(files(directory.path()), ["deck/front.png", "manifest.json"]);
+    }
+
+    #[test]
+    fn pruning() {
+        let directory = tempfile::tempdir().unwrap();
+        let stale = ["old/front-single.png", "old/back-single-256px.png"];
+        let kept = [
+            "catalog.csv",
+            "front-single.png",
+            "notes/todo.txt",
+            "deck/front-single.png.1234.tmp",
+        ];
+        for file in stale.iter().chain(&kept) {
+            let path = directory.path().join(relative_path(file));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"old").unwrap();
+        }
+        fs::create_dir(directory.path().join("empty")).unwrap();
+
+        let mut exporter = FileExporter::new(directory.path().into());
+        exporter.template = "{deck}/{side}-{content}-{preview}.{ext}".parse().unwrap();
+        exporter.prune = true;
+        exporter.export(artifact("deck")).unwrap();
+
+        // a failed run keeps everything
+        exporter.finish(false).unwrap();
+        assert_eq!(files(directory.path()).len(), 8);
+
+        exporter.finish(true).unwrap();
+        assert_eq!(
+            files(directory.path()),
+            [
+                "catalog.csv",
+                "deck/front-single.png",
+                "deck/front-single.png.1234.tmp",
+                "empty/",
+                "front-single.png",
+                "notes/todo.txt",
+            ]
+        );
+    }
 }
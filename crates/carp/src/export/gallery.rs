@@ -0,0 +1,528 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use super::{encode, Entry, Export, Store};
+use crate::{
+    artifact::{Amount, Artifact, Content},
+    Side,
+};
+
+/// Wraps an exporter and writes an HTML page showing everything it exported.
+///
+/// The page is self-contained apart from the images, so it can be opened straight from the export directory
+/// or from the bucket, without any tools.
+/// If previews were exported, the page shows the largest one and links to the full size image.
+pub struct Gallery<X: Export> {
+    pub inner: X,
+    /// The name the page is stored under.
+    pub name: String,
+    pub title: String,
+    /// Links to files below this directory are made relative to it,
+    /// so the page keeps working when the directory is moved or zipped.
+    pub base: Option<PathBuf>,
+    /// Also show every card on its own, cropped out of the sheets.
+    pub crop: bool,
+    /// The text of the cards of each deck, in order, listed below the deck.
+    pub cards: HashMap<String, Vec<String>>,
+    entries: Mutex<Vec<Entry<X::Output>>>,
+}
+
+impl<X: Export> Gallery<X> {
+    pub fn new(inner: X) -> Self {
+        Self {
+            inner,
+            name: "index.html".into(),
+            title: "Decks".into(),
+            base: None,
+            crop: false,
+            cards: HashMap::new(),
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Lists `cards` below `deck`.
+    pub fn describe(&mut self, deck: &str, cards: impl IntoIterator<Item = impl ToString>) {
+        self.cards.insert(
+            deck.into(),
+            cards.into_iter().map(|card| card.to_string()).collect(),
+        );
+    }
+}
+
+impl<X> Gallery<X>
+where
+    X: Export,
+    X::Output: AsRef<Path>,
+{
+    /// The `href` of `location`, relative if it's below [`Gallery::base`].
+    ///
+    /// Other local paths become `file://` URLs, locations that are URLs already are kept.
+    fn link(&self, location: &Path) -> String {
+        match self
+            .base
+            .as_deref()
+            .and_then(|base| location.strip_prefix(base).ok())
+        {
+            Some(relative) => {
+                let relative: Vec<_> = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect();
+                encode(&relative.join("/"))
+            }
+            None => {
+                let path = location.to_string_lossy().replace('\\', "/");
+                if path.contains("://") {
+                    path
+                } else if !location.is_absolute() {
+                    encode(&path)
+                } else if let Some((drive, rest)) = path.split_once(":/") {
+                    // Windows paths start with a drive letter, which keeps its colon
+                    format!("file:///{drive}:/{}", encode(rest))
+                } else {
+                    format!("file://{}", encode(&path))
+                }
+            }
+        }
+    }
+
+    /// The link to the image shown for `entry`, its largest preview if there is one.
+    fn source(&self, entry: &Entry<X::Output>, previews: &[&Entry<X::Output>]) -> String {
+        let preview = previews
+            .iter()
+            .filter(|preview| {
+                preview.deck == entry.deck
+                    && preview.side == entry.side
+                    && preview.content == entry.content
+                    && preview.amount == entry.amount
+            })
+            .max_by_key(|preview| preview.preview)
+            .copied()
+            .unwrap_or(entry);
+        self.link(preview.location.as_ref())
+    }
+
+    fn html(&self) -> Result<String, Box<dyn Error>> {
+        let entries = self.entries.lock().map_err(|e| e.to_string())?;
+        Ok(self.render(&entries)?)
+    }
+
+    fn render(&self, entries: &[Entry<X::Output>]) -> Result<String, fmt::Error> {
+        let (previews, entries): (Vec<_>, Vec<_>) =
+            entries.iter().partition(|entry| entry.preview.is_some());
+
+        let mut decks: Vec<(&str, Vec<&Entry<X::Output>>)> = Vec::new();
+        for entry in entries {
+            match decks.iter_mut().find(|(deck, _)| *deck == entry.deck) {
+                Some((_, entries)) => entries.push(entry),
+                None => decks.push((&entry.deck, vec![entry])),
+            }
+        }
+
+        let mut html = String::new();
+        writeln!(html, "<!DOCTYPE html>")?;
+        writeln!(html, "<html>\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(html, "<title>{}</title>", escape(&self.title))?;
+        writeln!(html, "<style>{STYLE}</style>\n</head>\n<body>")?;
+        writeln!(html, "<h1>{}</h1>", escape(&self.title))?;
+        writeln!(html, "<nav><ul>")?;
+        for (index, (deck, _)) in decks.iter().enumerate() {
+            writeln!(
+                html,
+                "<li><a href=\"#deck-{index}\">{}</a></li>",
+                escape(deck)
+            )?;
+        }
+        writeln!(html, "</ul></nav>")?;
+
+        for (index, (deck, mut entries)) in decks.into_iter().enumerate() {
+            entries.sort_by_key(|entry| (entry.side == Side::Back, page(entry.amount)));
+            let cards = self.cards.get(deck);
+
+            writeln!(html, "<section id=\"deck-{index}\">")?;
+            writeln!(html, "<h2>{}</h2>", escape(deck))?;
+            writeln!(html, "<div class=\"sheets\">")?;
+            for entry in &entries {
+                let link = escape(&self.link(entry.location.as_ref()));
+                let source = escape(&self.source(entry, &previews));
+                writeln!(
+                    html,
+                    "<figure><a href=\"{link}\"><img src=\"{source}\" loading=\"lazy\" alt=\"{}\"></a>\
+                    <figcaption>{} {} {}</figcaption></figure>",
+                    escape(&format!("{deck} {} {}", entry.side, entry.amount)),
+                    entry.side,
+                    entry.content,
+                    entry.amount
+                )?;
+            }
+            writeln!(html, "</div>")?;
+
+            if self.crop {
+                writeln!(html, "<div class=\"cards\">")?;
+                for entry in entries.iter().filter(|entry| entry.side == Side::Front) {
+                    self.render_cards(&mut html, entry, &previews, cards)?;
+                }
+                writeln!(html, "</div>")?;
+            }
+
+            if let Some(cards) = cards {
+                writeln!(html, "<ol>")?;
+                for card in cards {
+                    writeln!(html, "<li>{}</li>", escape(card))?;
+                }
+                writeln!(html, "</ol>")?;
+            }
+            writeln!(html, "</section>")?;
+        }
+
+        writeln!(html, "</body>\n</html>")?;
+        Ok(html)
+    }
+
+    /// Shows every card of a sheet on its own by cropping the sheet with CSS.
+    fn render_cards(
+        &self,
+        html: &mut String,
+        entry: &Entry<X::Output>,
+        previews: &[&Entry<X::Output>],
+        cards: Option<&Vec<String>>,
+    ) -> fmt::Result {
+        let Content::Sheet {
+            rows,
+            columns,
+            total,
+        } = entry.content
+        else {
+            return Ok(());
+        };
+        let (rows, columns) = (f64::from(rows), f64::from(columns));
+        let offset = (page(entry.amount) - 1) * rows as usize * columns as usize;
+        let aspect_ratio = entry.aspect_ratio.map_or(1.0, |ratio| ratio.0) * rows / columns;
+        let link = escape(&self.source(entry, previews));
+
+        for index in 0..usize::from(total) {
+            let (row, column) = ((index as f64 / columns).floor(), index as f64 % columns);
+            let text = cards
+                .and_then(|cards| cards.get(offset + index))
+                .map(|text| escape(text))
+                .unwrap_or_default();
+            writeln!(
+                html,
+                "<figure><div class=\"card\" title=\"{text}\" style=\"\
+                aspect-ratio: {aspect_ratio:.4}; \
+                background-image: url('{link}'); \
+                background-size: {}% {}%; \
+                background-position: {:.4}% {:.4}%\"></div>\
+                <figcaption>{}</figcaption></figure>",
+                columns * 100.0,
+                rows * 100.0,
+                if columns > 1.0 {
+                    column * 100.0 / (columns - 1.0)
+                } else {
+                    0.0
+                },
+                if rows > 1.0 {
+                    row * 100.0 / (rows - 1.0)
+                } else {
+                    0.0
+                },
+                offset + index + 1,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; background: #f4f4f4; color: #222; }
+section { margin-bottom: 3em; }
+figure { display: inline-block; margin: 0 1em 1em 0; vertical-align: top; }
+figcaption { font-size: small; color: #666; text-align: center; }
+.sheets img { max-width: 24em; max-height: 24em; border: 1px solid #ccc; background: white; }
+.card { width: 8em; border-radius: 0.4em; border: 1px solid #ccc; background-repeat: no-repeat; }
+ol li { white-space: pre-wrap; margin-bottom: 0.3em; }
+";
+
+/// The page of a sheet, counting from 1.
+fn page(amount: Amount) -> usize {
+    match amount {
+        Amount::Single => 1,
+        Amount::Multiple { index, .. } => usize::from(index),
+    }
+}
+
+/// Escapes `text` for use in HTML text and attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl<X> Export for Gallery<X>
+where
+    X: Store,
+    X::Output: Clone + AsRef<Path>,
+{
+    type Data = X::Data;
+    type Output = X::Output;
+
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        let artifact = self.inner.export(artifact)?;
+        self.entries
+            .lock()
+            .map_err(|e| e.to_string())?
+            .push(Entry::from(&artifact));
+        Ok(artifact)
+    }
+
+    fn finish_deck(&self, deck: &str) -> Result<(), Box<dyn Error>> {
+        self.inner.finish_deck(deck)
+    }
+
+    /// Finishes the wrapped exporter even if the page couldn't be stored, but not as `complete`.
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        let stored = self
+            .html()
+            .and_then(|html| self.inner.store(&self.name, html.as_bytes()));
+        let finished = self.inner.finish(complete && stored.is_ok());
+        stored.and(finished)
+    }
+}
+
+impl<X> Store for Gallery<X>
+where
+    X: Store,
+    X::Output: Clone + AsRef<Path>,
+{
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        self.inner.store(name, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+    use crate::{dimensions::AspectRatio, Backside};
+
+    /// Puts the artifacts below `export/` and keeps the stored files in memory.
+    #[derive(Default)]
+    struct Memory {
+        files: RefCell<Vec<String>>,
+        /// Fails every write, like a full disk.
+        full: bool,
+        finished: Cell<Option<bool>>,
+    }
+
+    impl Export for Memory {
+        type Data = PathBuf;
+        type Output = PathBuf;
+
+        fn export(
+            &self,
+            artifact: Artifact<Self::Data>,
+        ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+            let (name, artifact) = artifact.extract_data();
+            Ok(artifact.with_data(self.store(&name.to_string_lossy(), &[])?))
+        }
+
+        fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+            self.finished.set(Some(complete));
+            Ok(())
+        }
+    }
+
+    impl Store for Memory {
+        fn store(&self, name: &str, _data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+            if self.full {
+                return Err(format!("no space left for {name}").into());
+            }
+            self.files.borrow_mut().push(name.into());
+            Ok(Path::new("export").join(name))
+        }
+    }
+
+    fn entry(deck: &str, side: Side, content: Content, preview: Option<u32>) -> Entry<PathBuf> {
+        let name = match preview {
+            Some(preview) => format!("{deck}/{side}-{preview}px.png"),
+            None => format!("{deck}/{side}.png"),
+        };
+        Entry {
+            deck: deck.into(),
+            side,
+            shared: Backside::Shared,
+            content,
+            amount: Amount::Single,
+            aspect_ratio: Some(AspectRatio(1.5)),
+            extension: Some("png".into()),
+            preview,
+            location: Path::new("export").join(name),
+        }
+    }
+
+    /// A sheet of `total` cards, in `rows` and `columns`.
+    fn sheet(rows: u16, columns: u16, total: u16) -> Content {
+        Content::Sheet {
+            rows,
+            columns,
+            total,
+        }
+    }
+
+    fn gallery() -> Gallery<Memory> {
+        let mut gallery = Gallery::new(Memory::default());
+        gallery.base = Some("export".into());
+        gallery
+    }
+
+    #[test]
+    fn rendering() {
+        let html = gallery()
+            .render(&[
+                entry("Base Game", Side::Back, Content::Single, None),
+                entry("Base Game", Side::Front, sheet(2, 3, 5), None),
+                entry("Base Game", Side::Front, sheet(2, 3, 5), Some(256)),
+                entry("Expansion", Side::Front, Content::Single, None),
+                entry("Base Game", Side::Front, sheet(2, 3, 5), Some(1024)),
+            ])
+            .unwrap();
+
+        // the decks in the order they were exported
+        let base_game = html.find("<h2>Base Game</h2>").unwrap();
+        let expansion = html.find("<h2>Expansion</h2>").unwrap();
+        assert!(base_game < expansion);
+        assert_eq!(html.matches("<figure>").count(), 3);
+
+        // fronts before backs, showing the largest preview and linking to the full size sheet
+        let front = html
+            .find(
+                "<a href=\"Base%20Game/front.png\">\
+                <img src=\"Base%20Game/front-1024px.png\"",
+            )
+            .unwrap();
+        let back = html
+            .find("<a href=\"Base%20Game/back.png\"><img src=\"Base%20Game/back.png\"")
+            .unwrap();
+        assert!(base_game < front && front < back && back < expansion);
+    }
+
+    #[test]
+    fn cropping() {
+        let mut gallery = gallery();
+        gallery.crop = true;
+
+        // 2 rows of 3 cards, with the last one missing
+        let html = gallery
+            .render(&[entry("deck", Side::Front, sheet(2, 3, 5), None)])
+            .unwrap();
+        assert_eq!(html.matches("class=\"card\"").count(), 5);
+        assert!(html.contains(
+            "aspect-ratio: 1.0000; \
+            background-image: url('deck/front.png'); \
+            background-size: 300% 200%; \
+            background-position: 0.0000% 0.0000%\""
+        ));
+        assert!(html.contains("background-position: 100.0000% 0.0000%\""));
+        assert!(html.contains("background-position: 50.0000% 100.0000%\"></div><figcaption>5<"));
+
+        // a single row, which can't be positioned by dividing by rows - 1
+        let html = gallery
+            .render(&[entry("deck", Side::Front, sheet(1, 3, 3), None)])
+            .unwrap();
+        assert!(html.contains(
+            "aspect-ratio: 0.5000; \
+            background-image: url('deck/front.png'); \
+            background-size: 300% 100%; \
+            background-position: 50.0000% 0.0000%\""
+        ));
+    }
+
+    #[test]
+    fn linking() {
+        let gallery = gallery();
+        assert_eq!(
+            gallery.link(&Path::new("export").join("my deck #1").join("front.png")),
+            "my%20deck%20%231/front.png"
+        );
+        assert_eq!(
+            gallery.link(Path::new("https://bucket.example.com/my%20deck/front.png")),
+            "https://bucket.example.com/my%20deck/front.png"
+        );
+        assert_eq!(
+            gallery.link(Path::new("other/my deck.png")),
+            "other/my%20deck.png"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            gallery.link(Path::new("/tmp/my deck.png")),
+            "file:///tmp/my%20deck.png"
+        );
+        #[cfg(windows)]
+        assert_eq!(
+            gallery.link(Path::new(r"C:\decks\my deck.png")),
+            "file:///C:/decks/my%20deck.png"
+        );
+    }
+
+    #[test]
+    fn finishing() {
+        let gallery = gallery();
+        gallery
+            .export(Artifact {
+                deck: "deck".into(),
+                shared: Backside::Shared,
+                data: PathBuf::from("deck/front.png"),
+                side: Side::Front,
+                content: Content::Single,
+                amount: Amount::Single,
+                aspect_ratio: None,
+                extension: Some("png".into()),
+                preview: None,
+            })
+            .unwrap();
+        gallery.finish(true).unwrap();
+        assert_eq!(
+            *gallery.inner.files.borrow(),
+            ["deck/front.png", "index.html"]
+        );
+        assert_eq!(gallery.inner.finished.get(), Some(true));
+    }
+
+    #[test]
+    fn finishing_after_a_failed_store() {
+        let gallery = Gallery::new(Memory {
+            full: true,
+            ..Default::default()
+        });
+        let error = gallery.finish(true).unwrap_err();
+        assert_eq!(error.to_string(), "no space left for index.html");
+        // the inner exporter still finishes, but doesn't clean up after an incomplete run
+        assert_eq!(gallery.inner.finished.get(), Some(false));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            escape("<b>\"Tom\" & 'Jerry'</b>"),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use std::{error::Error, sync::Mutex};
+
+use serde::Serialize;
+
+use super::{Export, Store};
+use crate::{
+    artifact::{Amount, Artifact, Content},
+    dimensions::AspectRatio,
+    Backside, Side,
+};
+
+/// Wraps an exporter and records everything it exports.
+///
+/// At the end of the run the record is stored as JSON through the wrapped exporter,
+/// so it ends up next to the files on disk or as an object in the bucket.
+pub struct Manifest<X: Export> {
+    pub inner: X,
+    /// The name the manifest is stored under.
+    pub name: String,
+    entries: Mutex<Vec<Entry<X::Output>>>,
+}
+
+impl<X: Export> Manifest<X> {
+    pub fn new(inner: X) -> Self {
+        Self {
+            inner,
+            name: "manifest.json".into(),
+            entries: Mutex::default(),
+        }
+    }
+}
+
+/// A single exported artifact in a [Manifest].
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry<Location> {
+    pub deck: String,
+    pub side: Side,
+    pub shared: Backside,
+    pub content: Content,
+    pub amount: Amount,
+    pub aspect_ratio: Option<AspectRatio>,
+    pub extension: Option<String>,
+    /// The size of the preview, if this is one.
+    pub preview: Option<u32>,
+    /// Where the exporter put the artifact, e.g. a path or a URL.
+    pub location: Location,
+}
+
+impl<Location: Clone> From<&Artifact<Location>> for Entry<Location> {
+    fn from(artifact: &Artifact<Location>) -> Self {
+        Self {
+            deck: artifact.deck.clone(),
+            side: artifact.side,
+            shared: artifact.shared,
+            content: artifact.content,
+            amount: artifact.amount,
+            aspect_ratio: artifact.aspect_ratio,
+            extension: artifact.extension.clone(),
+            preview: artifact.preview,
+            location: artifact.data.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a, Location> {
+    artifacts: &'a [Entry<Location>],
+}
+
+impl<X> Manifest<X>
+where
+    X: Export,
+    X::Output: Serialize,
+{
+    fn json(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let entries = self.entries.lock().map_err(|e| e.to_string())?;
+        Ok(serde_json::to_vec_pretty(&Document {
+            artifacts: &entries,
+        })?)
+    }
+}
+
+impl<X> Export for Manifest<X>
+where
+    X: Store,
+    X::Output: Clone + Serialize,
+{
+    type Data = X::Data;
+    type Output = X::Output;
+
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        let artifact = self.inner.export(artifact)?;
+        self.entries
+            .lock()
+            .map_err(|e| e.to_string())?
+            .push(Entry::from(&artifact));
+        Ok(artifact)
+    }
+
+    fn finish_deck(&self, deck: &str) -> Result<(), Box<dyn Error>> {
+        self.inner.finish_deck(deck)
+    }
+
+    /// Finishes the wrapped exporter even if the manifest couldn't be stored, but not as `complete`.
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        let stored = self
+            .json()
+            .and_then(|json| self.inner.store(&self.name, &json));
+        let finished = self.inner.finish(complete && stored.is_ok());
+        stored.and(finished)
+    }
+}
+
+impl<X> Store for Manifest<X>
+where
+    X: Store,
+    X::Output: Clone + Serialize,
+{
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        self.inner.store(name, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Keeps the files in memory and names them after the artifacts.
+    #[derive(Default)]
+    struct Memory {
+        files: RefCell<Vec<(String, Vec<u8>)>>,
+        /// Fails every write, like a full disk.
+        full: bool,
+        finished: Cell<Option<bool>>,
+    }
+
+    impl Export for Memory {
+        type Data = Vec<u8>;
+        type Output = String;
+
+        fn export(
+            &self,
+            artifact: Artifact<Self::Data>,
+        ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+            let name = format!("{artifact}.png");
+            let (data, artifact) = artifact.extract_data();
+            self.store(&name, &data)?;
+            Ok(artifact.with_data(name))
+        }
+
+        fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+            self.finished.set(Some(complete));
+            Ok(())
+        }
+    }
+
+    impl Store for Memory {
+        fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+            if self.full {
+                return Err(format!("no space left for {name}").into());
+            }
+            self.files.borrow_mut().push((name.into(), data.into()));
+            Ok(name.into())
+        }
+    }
+
+    #[test]
+    fn manifest() {
+        let manifest = Manifest::new(Memory::default());
+        let sheet = Artifact {
+            deck: "deck".into(),
+            shared: Backside::Shared,
+            data: vec![1, 2, 3],
+            side: Side::Front,
+            content: Content::Sheet {
+                rows: 7,
+                columns: 10,
+                total: 3,
+            },
+            amount: Amount::Single,
+            aspect_ratio: Some(AspectRatio(0.5)),
+            extension: Some("png".into()),
+            preview: None,
+        };
+        let preview = Artifact {
+            preview: Some(256),
+            ..sheet.clone()
+        };
+        for artifact in [sheet, preview] {
+            manifest.export(artifact).unwrap();
+        }
+        assert_eq!(manifest.inner.files.borrow().len(), 2);
+        manifest.finish(true).unwrap();
+        assert_eq!(manifest.inner.finished.get(), Some(true));
+
+        let files = manifest.inner.files.borrow();
+        let (name, json) = files.last().unwrap();
+        assert_eq!(name, "manifest.json");
+        let json: Value = serde_json::from_slice(json).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "artifacts": [
+                    {
+                        "deck": "deck",
+                        "side": "front",
+                        "shared": "shared",
+                        "content": {"sheet": {"rows": 7, "columns": 10, "total": 3}},
+                        "amount": "single",
+                        "aspect_ratio": 0.5,
+                        "extension": "png",
+                        "preview": null,
+                        "location": "deck-front-r7c10t3-1of1.png",
+                    },
+                    {
+                        "deck": "deck",
+                        "side": "front",
+                        "shared": "shared",
+                        "content": {"sheet": {"rows": 7, "columns": 10, "total": 3}},
+                        "amount": "single",
+                        "aspect_ratio": 0.5,
+                        "extension": "png",
+                        "preview": 256,
+                        "location": "deck-front-r7c10t3-1of1-256px.png",
+                    },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn finishing_after_a_failed_store() {
+        let manifest = Manifest::new(Memory {
+            full: true,
+            ..Default::default()
+        });
+        let error = manifest.finish(true).unwrap_err();
+        assert_eq!(error.to_string(), "no space left for manifest.json");
+        // the inner exporter still finishes, but doesn't clean up after an incomplete run
+        assert_eq!(manifest.inner.finished.get(), Some(false));
+    }
+}
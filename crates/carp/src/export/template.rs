@@ -0,0 +1,418 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use crate::{artifact::Artifact, hash::content_hash};
+
+/// A file name with placeholders that are filled in from an [Artifact].
+///
+/// | placeholder | replaced with                                   |
+/// |-------------|-------------------------------------------------|
+/// | `{deck}`    | the name of the deck, see [sanitize]            |
+/// | `{side}`    | `front` or `back`                               |
+/// | `{content}` | e.g. `r7c10t70` or `single`                     |
+/// | `{amount}`  | e.g. `1of2`                                     |
+/// | `{hash}`    | the [content_hash] of the data                  |
+/// | `{preview}` | e.g. `256px` for previews, a `-` right before it is dropped for full size images |
+/// | `{ext}`     | the extension, a `.` right before it is dropped if there is none |
+///
+/// A `/` separates directories, so `{deck}/{side}-{amount}.{ext}` puts each deck into its own directory.
+/// `\` and `:` aren't allowed, so templates mean the same on every platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Deck,
+    Side,
+    Content,
+    Amount,
+    Hash,
+    Preview,
+    Extension,
+}
+
+impl Template {
+    /// The naming [Artifact]s have always used: `deck-front-r7c10t70-1of2.png`,
+    /// previews get a suffix: `deck-front-r7c10t70-1of2-256px.png`
+    pub const DEFAULT: &'static str = "{deck}-{side}-{content}-{amount}-{preview}.{ext}";
+
+    /// Names artifacts only after their data, so identical images always get the same name
+    /// and names only change when the image does.
+    pub const CONTENT_ADDRESSED: &'static str = "{hash}.{ext}";
+
+    /// A template for [`Template::CONTENT_ADDRESSED`] naming.
+    #[must_use]
+    pub fn content_addressed() -> Self {
+        Self::CONTENT_ADDRESSED
+            .parse()
+            .expect("the content addressed template is valid")
+    }
+
+    /// Whether previews get names of their own instead of overwriting the full size image.
+    #[must_use]
+    pub fn names_previews(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Preview | Part::Hash))
+    }
+
+    pub fn render(&self, artifact: &Artifact<impl AsRef<[u8]>>) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Deck => name.push_str(&sanitize(&artifact.deck)),
+                Part::Side => name.push_str(&artifact.side.to_string()),
+                Part::Content => name.push_str(&artifact.content.to_string()),
+                Part::Amount => name.push_str(&artifact.amount.to_string()),
+                Part::Hash => name.push_str(&content_hash(artifact.data.as_ref())),
+                Part::Preview => match artifact.preview {
+                    Some(size) => name.push_str(&format!("{size}px")),
+                    None => {
+                        if name.ends_with('-') {
+                            name.pop();
+                        }
+                    }
+                },
+                Part::Extension => match artifact.extension {
+                    Some(ref extension) => name.push_str(&sanitize(extension)),
+                    None => {
+                        if name.ends_with('.') {
+                            name.pop();
+                        }
+                    }
+                },
+            }
+        }
+        name
+    }
+
+    /// Whether `name` looks like this template [rendered](Template::render) it,
+    /// with `/` between directories.
+    ///
+    /// Used to only ever prune files that an export could have written.
+    #[must_use]
+    pub fn matches(&self, name: &str) -> bool {
+        matches(&self.parts, name)
+    }
+
+    /// The [rendered](Template::render) name as a path relative to the export directory.
+    #[must_use]
+    pub fn path(&self, artifact: &Artifact<impl AsRef<[u8]>>) -> PathBuf {
+        relative_path(&self.render(artifact))
+    }
+}
+
+/// Whether `name` starts with something `part` renders to and the `rest` matches the remainder.
+fn matches(parts: &[Part], name: &str) -> bool {
+    let Some((part, rest)) = parts.split_first() else {
+        return name.is_empty();
+    };
+    if let Part::Text(text) = part {
+        if name
+            .strip_prefix(text.as_str())
+            .is_some_and(|name| matches(rest, name))
+        {
+            return true;
+        }
+        // the `-` before a missing preview and the `.` before a missing extension are dropped
+        let dropped = match rest.first() {
+            Some(Part::Preview) => '-',
+            Some(Part::Extension) => '.',
+            _ => return false,
+        };
+        return text
+            .strip_suffix(dropped)
+            .and_then(|text| name.strip_prefix(text))
+            .is_some_and(|name| matches(&rest[1..], name));
+    }
+
+    (0..=name.len())
+        .filter(|&end| name.is_char_boundary(end))
+        .any(|end| renders(part, &name[..end]) && matches(rest, &name[end..]))
+}
+
+/// Whether `part` can render to `value`.
+fn renders(part: &Part, value: &str) -> bool {
+    let digits = |value: &str| !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit());
+    match part {
+        Part::Text(text) => value == text,
+        Part::Deck => !value.is_empty() && !value.contains('/'),
+        Part::Side => value == "front" || value == "back",
+        Part::Content => {
+            value == "single"
+                || value
+                    .strip_prefix('r')
+                    .and_then(|value| value.split_once('c'))
+                    .and_then(|(rows, value)| Some((rows, value.split_once('t')?)))
+                    .is_some_and(|(rows, (columns, total))| {
+                        digits(rows) && digits(columns) && digits(total)
+                    })
+        }
+        Part::Amount => value
+            .split_once("of")
+            .is_some_and(|(index, total)| digits(index) && digits(total)),
+        Part::Hash => !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_hexdigit()),
+        Part::Preview => value.is_empty() || value.strip_suffix("px").is_some_and(digits),
+        Part::Extension => value.bytes().all(|byte| byte.is_ascii_alphanumeric()),
+    }
+}
+
+/// Turns a name with `/` between directories into a relative path.
+///
+/// The parts are pushed one at a time, as a `/` isn't a separator below verbatim `\\?\` paths on Windows.
+pub(crate) fn relative_path(name: &str) -> PathBuf {
+    name.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect()
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self::DEFAULT
+            .parse()
+            .expect("the default template is valid")
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].into()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("invalid template: {s} (unclosed {{)"))?
+                + start;
+            parts.push(match &rest[start + 1..end] {
+                "deck" => Part::Deck,
+                "side" => Part::Side,
+                "content" => Part::Content,
+                "amount" => Part::Amount,
+                "hash" => Part::Hash,
+                "preview" => Part::Preview,
+                "ext" => Part::Extension,
+                unknown => {
+                    return Err(format!(
+                        "invalid template: {s} (unknown placeholder {{{unknown}}})"
+                    ))
+                }
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.into()));
+        }
+
+        if s.contains(['\\', ':']) {
+            return Err(format!(
+                "invalid template: {s} (use / to separate directories, \\ and : aren't allowed)"
+            ));
+        }
+        if s.starts_with('/') || s.split('/').any(|segment| segment == "..") {
+            return Err(format!(
+                "invalid template: {s} (names have to stay inside the export directory)"
+            ));
+        }
+
+        Ok(Self {
+            source: s.into(),
+            parts,
+        })
+    }
+}
+
+impl Display for Template {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Turns `name` into something that can safely be used as a single file name on any platform.
+///
+/// Path separators and characters Windows doesn't allow are replaced with `_`,
+/// as are names that Windows reserves for devices, like `CON`.
+#[must_use]
+pub fn sanitize(name: &str) -> String {
+    let mut sanitized: String = name
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows silently drops trailing dots and spaces
+    while sanitized.ends_with(['.', ' ']) {
+        sanitized.pop();
+    }
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    let reserved = matches!(
+        stem.to_ascii_uppercase().as_str(),
+        "CON" | "PRN" | "AUX" | "NUL"
+    ) || (stem.is_ascii()
+        && stem.len() == 4
+        && ["COM", "LPT"].contains(&stem[..3].to_ascii_uppercase().as_str())
+        && stem.as_bytes()[3].is_ascii_digit());
+
+    if sanitized.is_empty() || reserved {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        artifact::{Amount, Content},
+        Backside, Side,
+    };
+
+    fn artifact(deck: &str, extension: Option<&str>) -> Artifact<Vec<u8>> {
+        Artifact {
+            deck: deck.into(),
+            shared: Backside::Shared,
+            data: b"hello".to_vec(),
+            side: Side::Front,
+            content: Content::Sheet {
+                rows: 7,
+                columns: 10,
+                total: 70,
+            },
+            amount: Amount::Multiple { index: 1, total: 2 },
+            aspect_ratio: None,
+            extension: extension.map(Into::into),
+            preview: None,
+        }
+    }
+
+    #[test]
+    fn default_template() {
+        let template = Template::default();
+
+        assert_eq!(
+            template.render(&artifact("deck", Some("png"))),
+            "deck-front-r7c10t70-1of2.png"
+        );
+        assert_eq!(
+            template.render(&artifact("deck", None)),
+            "deck-front-r7c10t70-1of2"
+        );
+
+        let mut preview = artifact("deck", Some("png"));
+        preview.preview = Some(256);
+        assert_eq!(
+            template.render(&preview),
+            "deck-front-r7c10t70-1of2-256px.png"
+        );
+        assert!(template.names_previews());
+        assert!(!"{deck}.{ext}".parse::<Template>().unwrap().names_previews());
+    }
+
+    #[test]
+    fn directories_and_hash() {
+        let template: Template = "{deck}/{hash}.{ext}".parse().unwrap();
+
+        assert_eq!(
+            template.render(&artifact("a/b", Some("png"))),
+            format!("a_b/{}.png", content_hash(b"hello"))
+        );
+    }
+
+    #[test]
+    fn matching() {
+        let template = Template::default();
+        let mut preview = artifact("deck", Some("png"));
+        preview.preview = Some(256);
+        for artifact in [
+            artifact("Base Game", Some("png")),
+            artifact("deck", None),
+            preview,
+        ] {
+            assert!(template.matches(&template.render(&artifact)));
+        }
+        assert!(template.matches("deck-back-single-1of1.webp"));
+        assert!(!template.matches("catalog.csv"));
+        assert!(!template.matches("manifest.json"));
+        assert!(!template.matches("deck-front-r7c10t70-1of2.png.1234.tmp"));
+        assert!(!template.matches("notes/deck-front-single-1of1.png"));
+
+        let template: Template = "{deck}/{hash}.{ext}".parse().unwrap();
+        assert!(template.matches(&template.render(&artifact("deck", Some("jpg")))));
+        assert!(!template.matches("deck/holiday.jpg"));
+        assert!(!template.matches("deck.jpg"));
+    }
+
+    #[test]
+    fn paths() {
+        let template: Template = "{deck}/sheets/{side}.{ext}".parse().unwrap();
+        let path = template.path(&artifact("a/b", Some("png")));
+        assert_eq!(path, Path::new("a_b").join("sheets").join("front.png"));
+        assert_eq!(path.components().count(), 3);
+
+        assert_eq!(relative_path("a//./b.png"), Path::new("a").join("b.png"));
+        assert_eq!(relative_path("manifest.json"), Path::new("manifest.json"));
+    }
+
+    #[test]
+    fn content_addressed() {
+        let template = Template::content_addressed();
+        let mut other = artifact("other", Some("png"));
+        other.side = Side::Back;
+
+        assert_eq!(
+            template.render(&artifact("deck", Some("png"))),
+            template.render(&other)
+        );
+        other.data = b"world".to_vec();
+        assert_ne!(
+            template.render(&artifact("deck", Some("png"))),
+            template.render(&other)
+        );
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!("{deck".parse::<Template>().is_err());
+        assert!("{colour}".parse::<Template>().is_err());
+        assert!("../{deck}".parse::<Template>().is_err());
+        assert!("/{deck}".parse::<Template>().is_err());
+        assert!("..\\..\\{deck}".parse::<Template>().is_err());
+        assert!("\\{deck}".parse::<Template>().is_err());
+        assert!("C:\\{deck}".parse::<Template>().is_err());
+        assert!("C:{deck}".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn sanitizing() {
+        assert_eq!(
+            sanitize("Cards: Against/Humanity?"),
+            "Cards_ Against_Humanity_"
+        );
+        assert_eq!(sanitize(" trailing dots... "), "trailing dots");
+        assert_eq!(sanitize("con"), "_con");
+        assert_eq!(sanitize("LPT1.txt"), "_LPT1.txt");
+        assert_eq!(sanitize("Console"), "Console");
+        assert_eq!(sanitize(".."), "_");
+        assert_eq!(sanitize(""), "_");
+    }
+}
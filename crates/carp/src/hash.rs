@@ -0,0 +1,12 @@
+use sha2::{Digest, Sha256};
+
+/// A short, stable fingerprint of `data` as hex, suitable for file names and object keys.
+///
+/// Identical bytes always produce the same hash, across runs and versions.
+#[must_use]
+pub fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
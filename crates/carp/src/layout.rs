@@ -0,0 +1,15 @@
+use crate::{artifact::Artifact, renderer::Render, Card as CardTrait, Deck as DeckTrait, Result};
+
+/// A [Layout] decides which images a [Deck](DeckTrait) is turned into
+/// and renders them one after another with a [Render]er.
+pub trait Layout {
+    fn build<'a, Format, Deck, Card>(
+        &'a self,
+        deck: &'a Deck,
+        renderer: &'a impl Render<Output = Format>,
+    ) -> impl Iterator<Item = Result<Artifact<Format>>> + 'a
+    where
+        Format: 'a,
+        Deck: DeckTrait<Card>,
+        Card: CardTrait<Deck = Deck> + 'a;
+}
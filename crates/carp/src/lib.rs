@@ -3,8 +3,15 @@ use dimensions::AspectRatio;
 pub mod artifact;
 pub mod device;
 pub mod dimensions;
+pub mod event;
 pub mod export;
+pub mod hash;
+pub mod layout;
+pub mod metrics;
+pub mod pipeline;
+pub mod pixels;
 pub mod renderer;
+pub mod singles;
 pub mod tts;
 
 pub const BASE_RESOLUTION: u32 = 4096;
@@ -26,8 +33,10 @@ mod card {
 
     use super::dimensions::Dimensions;
     use piet_common::RenderContext;
+    use serde::Serialize;
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+    #[serde(rename_all = "lowercase")]
     pub enum Side {
         Front,
         Back,
@@ -67,9 +76,11 @@ mod deck {
     use std::fmt::Display;
 
     use super::Card as CardTrait;
+    use serde::Serialize;
 
     /// The back of a card can be the same across a [Deck] ([`Backside::Shared`]) or each [Card] can have its own one ([`Backside::Unique`]).
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+    #[serde(rename_all = "lowercase")]
     pub enum Backside {
         #[default]
         Shared,
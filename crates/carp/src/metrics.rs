@@ -0,0 +1,306 @@
+use std::{cell::RefCell, io::Write, time::Duration};
+
+use serde::{Serialize, Serializer};
+
+use crate::{
+    artifact::{Amount, Artifact, Content},
+    event::{Event, Observer},
+    renderer::Timings,
+    Result, Side,
+};
+
+/// Collects the measurements of a [Pipeline](crate::pipeline::Pipeline) run into a [RunMetrics] report.
+///
+/// The pipeline takes ownership of its observers,
+/// so hand it an `Rc<Metrics>` and keep a clone around to read the report afterwards.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    run: RefCell<RunMetrics>,
+}
+
+impl Metrics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn report(&self) -> RunMetrics {
+        self.run.borrow().clone()
+    }
+
+    /// Writes the report as pretty printed JSON. All durations are in seconds.
+    pub fn write_json(&self, writer: impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(writer, &*self.run.borrow())?;
+        Ok(())
+    }
+}
+
+impl Observer for Metrics {
+    fn notify(&self, event: &Event<'_>) {
+        let mut run = self.run.borrow_mut();
+        match *event {
+            Event::RunStarted { .. } => *run = RunMetrics::default(),
+            Event::DeckStarted { deck, .. } => run.decks.push(DeckMetrics {
+                deck: deck.into(),
+                ..Default::default()
+            }),
+            Event::SheetRendered {
+                artifact,
+                took,
+                timings,
+            } => {
+                if let Some(deck) = run.decks.last_mut() {
+                    deck.artifacts.push(ArtifactMetrics {
+                        name: artifact.to_string(),
+                        side: artifact.side,
+                        content: artifact.content,
+                        amount: artifact.amount,
+                        preview: None,
+                        render: Some(RenderMetrics { took, timings }),
+                        scale: None,
+                        encode: None,
+                        export: None,
+                    });
+                }
+            }
+            Event::PreviewScaled { artifact, took } => {
+                if let Some(deck) = run.decks.last_mut() {
+                    deck.artifacts.push(ArtifactMetrics {
+                        name: artifact.to_string(),
+                        side: artifact.side,
+                        content: artifact.content,
+                        amount: artifact.amount,
+                        preview: artifact.preview,
+                        render: None,
+                        scale: Some(ScaleMetrics { took }),
+                        encode: None,
+                        export: None,
+                    });
+                }
+            }
+            Event::ArtifactEncoded {
+                artifact,
+                took,
+                bytes,
+            } => {
+                if let Some(metrics) = run.artifact(artifact) {
+                    metrics.encode = Some(EncodeMetrics { took, bytes });
+                }
+            }
+            Event::ArtifactExported { artifact, took } => {
+                if let Some(metrics) = run.artifact(artifact) {
+                    metrics.export = Some(ExportMetrics { took });
+                }
+            }
+            Event::Failed { failure, .. } => match (failure.deck.is_some(), run.decks.last_mut()) {
+                (true, Some(deck)) => deck.failures.push(failure.to_string()),
+                _ => run.failures.push(failure.to_string()),
+            },
+            Event::DeckFinished { took, .. } => {
+                if let Some(deck) = run.decks.last_mut() {
+                    deck.took = took;
+                }
+            }
+            Event::RunFinished { took } => run.took = took,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunMetrics {
+    #[serde(serialize_with = "seconds")]
+    pub took: Duration,
+    pub decks: Vec<DeckMetrics>,
+    pub failures: Vec<String>,
+}
+
+impl RunMetrics {
+    fn artifact(&mut self, artifact: &Artifact<()>) -> Option<&mut ArtifactMetrics> {
+        let name = artifact.to_string();
+        self.decks
+            .last_mut()?
+            .artifacts
+            .iter_mut()
+            .rev()
+            .find(|metrics| metrics.name == name)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeckMetrics {
+    pub deck: String,
+    #[serde(serialize_with = "seconds")]
+    pub took: Duration,
+    pub artifacts: Vec<ArtifactMetrics>,
+    pub failures: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactMetrics {
+    pub name: String,
+    pub side: Side,
+    pub content: Content,
+    pub amount: Amount,
+    /// The size of the preview, if this is one.
+    pub preview: Option<u32>,
+    pub render: Option<RenderMetrics>,
+    pub scale: Option<ScaleMetrics>,
+    pub encode: Option<EncodeMetrics>,
+    pub export: Option<ExportMetrics>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RenderMetrics {
+    #[serde(serialize_with = "seconds")]
+    pub took: Duration,
+    pub timings: Option<Timings>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ScaleMetrics {
+    #[serde(serialize_with = "seconds")]
+    pub took: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct EncodeMetrics {
+    #[serde(serialize_with = "seconds")]
+    pub took: Duration,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ExportMetrics {
+    #[serde(serialize_with = "seconds")]
+    pub took: Duration,
+}
+
+/// Serializes a [Duration] as fractional seconds.
+pub(crate) fn seconds<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        pipeline::{Failure, Stage},
+        Backside,
+    };
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn collecting() {
+        let sheet = Artifact {
+            deck: "deck".into(),
+            shared: Backside::Shared,
+            data: (),
+            side: Side::Front,
+            content: Content::Single,
+            amount: Amount::Single,
+            aspect_ratio: None,
+            extension: Some("png".into()),
+            preview: None,
+        };
+        let preview = Artifact {
+            preview: Some(256),
+            ..sheet.clone()
+        };
+        let failure = Failure {
+            deck: Some("deck".into()),
+            artifact: Some(preview.to_string()),
+            stage: Stage::Export,
+            error: "no space left".into(),
+        };
+        let timings = Timings {
+            wait: ms(1),
+            draw: ms(2),
+            copy: ms(3),
+        };
+
+        let metrics = Metrics::new();
+        // a second run starts over
+        for _ in 0..2 {
+            for event in [
+                Event::RunStarted { decks: 1 },
+                Event::DeckStarted {
+                    deck: "deck",
+                    index: 0,
+                },
+                Event::SheetRendered {
+                    artifact: &sheet,
+                    took: ms(6),
+                    timings: Some(timings),
+                },
+                Event::PreviewScaled {
+                    artifact: &preview,
+                    took: ms(1),
+                },
+                Event::ArtifactEncoded {
+                    artifact: &sheet,
+                    took: ms(4),
+                    bytes: 42,
+                },
+                Event::ArtifactExported {
+                    artifact: &sheet,
+                    took: ms(5),
+                },
+                Event::Failed {
+                    failure: &failure,
+                    took: ms(1),
+                },
+                Event::DeckFinished {
+                    deck: "deck",
+                    took: ms(20),
+                },
+                Event::RunFinished { took: ms(25) },
+            ] {
+                metrics.notify(&event);
+            }
+        }
+
+        let report = metrics.report();
+        let [deck] = &report.decks[..] else {
+            panic!("{report:?}");
+        };
+        assert_eq!(deck.artifacts.len(), 2);
+        assert_eq!(
+            deck.failures,
+            ["deck (deck-front-single-1of1-256px): couldn't export: no space left"]
+        );
+        assert!(report.failures.is_empty());
+
+        let mut json = Vec::new();
+        metrics.write_json(&mut json).unwrap();
+        let json: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["took"], 0.025);
+        assert_eq!(
+            json["decks"][0]["artifacts"][0],
+            json!({
+                "name": "deck-front-single-1of1",
+                "side": "front",
+                "content": "single",
+                "amount": "single",
+                "preview": null,
+                "render": {
+                    "took": 0.006,
+                    "timings": {"wait": 0.001, "draw": 0.002, "copy": 0.003},
+                },
+                "scale": null,
+                "encode": {"took": 0.004, "bytes": 42},
+                "export": {"took": 0.005},
+            })
+        );
+        assert_eq!(json["decks"][0]["artifacts"][1]["preview"], 256);
+        assert_eq!(json["decks"][0]["artifacts"][1]["export"], Value::Null);
+    }
+}
@@ -0,0 +1,692 @@
+use std::{
+    fmt::{Display, Formatter},
+    time::Instant,
+};
+
+use crate::{
+    artifact::Artifact,
+    event::{Event, Observer},
+    export::Export,
+    layout::Layout,
+    renderer::Render,
+    Card as CardTrait, Deck as DeckTrait, Error,
+};
+
+/// Runs decks through a [Layout], a [Render]er, an encoder and an exporter.
+///
+/// A failing deck or artifact doesn't abort the run,
+/// everything that went wrong ends up in the returned [Report] instead.
+/// [Observer]s can follow along while the pipeline is running.
+pub struct Pipeline<L, R, E, X> {
+    pub layout: L,
+    pub renderer: R,
+    /// Turns the rendered images into bytes, e.g. PNG files.
+    pub encoder: E,
+    /// Puts the encoded artifacts somewhere, e.g. on disk or into a bucket.
+    ///
+    /// Use [`Export::then`] to run several exporters in sequence.
+    pub exporter: X,
+    pub observers: Vec<Box<dyn Observer>>,
+    /// The sizes of the previews made of every artifact, see [`Pipeline::with_previews`].
+    pub previews: Vec<u32>,
+}
+
+impl<L, R, E, X> Pipeline<L, R, E, X>
+where
+    L: Layout,
+    R: Render,
+    E: Export<Data = R::Output>,
+    E::Output: AsRef<[u8]>,
+    X: Export<Data = E::Output>,
+{
+    pub fn new(layout: L, renderer: R, encoder: E, exporter: X) -> Self {
+        Self {
+            layout,
+            renderer,
+            encoder,
+            exporter,
+            observers: Vec::new(),
+            previews: Vec::new(),
+        }
+    }
+
+    /// Also encodes and exports a smaller copy of every artifact for each of the `sizes`,
+    /// the length of their longer side in pixels.
+    ///
+    /// Previews are scaled down from the rendered image, so nothing is rendered twice.
+    /// Sizes at least as large as the rendered image are skipped for that artifact.
+    #[must_use]
+    pub fn with_previews(mut self, sizes: impl IntoIterator<Item = u32>) -> Self {
+        self.previews = sizes.into_iter().collect();
+        self
+    }
+
+    #[must_use]
+    pub fn observe(mut self, observer: impl Observer + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    pub fn run<'d, Deck, Card>(
+        &self,
+        decks: impl IntoIterator<Item = &'d Deck>,
+    ) -> Report<X::Output>
+    where
+        Deck: DeckTrait<Card> + 'd,
+        Card: CardTrait<Deck = Deck> + 'd,
+    {
+        let run_start = Instant::now();
+        let decks: Vec<_> = decks.into_iter().collect();
+        self.emit(&Event::RunStarted { decks: decks.len() });
+
+        let mut report = Report {
+            decks: decks
+                .into_iter()
+                .enumerate()
+                .map(|(index, deck)| self.run_deck(index, deck))
+                .collect(),
+            failures: Vec::new(),
+        };
+
+        let start = Instant::now();
+        let mut complete = report.is_success();
+        if let Err(error) = self.encoder.finish(complete) {
+            self.fail(
+                &mut report.failures,
+                None,
+                None,
+                Stage::Finish,
+                error,
+                start,
+            );
+            complete = false;
+        }
+        if let Err(error) = self.exporter.finish(complete) {
+            self.fail(
+                &mut report.failures,
+                None,
+                None,
+                Stage::Finish,
+                error,
+                start,
+            );
+        }
+
+        self.emit(&Event::RunFinished {
+            took: run_start.elapsed(),
+        });
+        report
+    }
+
+    fn run_deck<Deck, Card>(&self, index: usize, deck: &Deck) -> DeckReport<X::Output>
+    where
+        Deck: DeckTrait<Card>,
+        Card: CardTrait<Deck = Deck>,
+    {
+        let deck_start = Instant::now();
+        let name = deck.name();
+        let mut exported = Vec::new();
+        let mut failures = Vec::new();
+        self.emit(&Event::DeckStarted { deck: name, index });
+
+        let mut artifacts = self.layout.build(deck, &self.renderer);
+        loop {
+            let start = Instant::now();
+            let artifact = match artifacts.next() {
+                Some(Ok(artifact)) => artifact,
+                Some(Err(error)) => {
+                    self.fail(&mut failures, Some(name), None, Stage::Render, error, start);
+                    continue;
+                }
+                None => break,
+            };
+            self.emit(&Event::SheetRendered {
+                artifact: &artifact.meta(),
+                took: start.elapsed(),
+                timings: self.renderer.last_timings(),
+            });
+
+            let previews: Vec<_> = self
+                .previews
+                .iter()
+                .filter_map(|&size| {
+                    let start = Instant::now();
+                    let image = self.renderer.downscale(&artifact.data, size)?;
+                    let preview = Artifact {
+                        preview: Some(size),
+                        ..artifact.meta()
+                    };
+                    self.emit(&Event::PreviewScaled {
+                        artifact: &preview,
+                        took: start.elapsed(),
+                    });
+                    Some(preview.with_data(image))
+                })
+                .collect();
+
+            for artifact in std::iter::once(artifact).chain(previews) {
+                if let Some(artifact) = self.deliver(artifact, name, &mut failures) {
+                    exported.push(artifact);
+                }
+            }
+        }
+
+        let start = Instant::now();
+        for result in [
+            self.encoder.finish_deck(name),
+            self.exporter.finish_deck(name),
+        ] {
+            if let Err(error) = result {
+                self.fail(&mut failures, Some(name), None, Stage::Finish, error, start);
+            }
+        }
+
+        self.emit(&Event::DeckFinished {
+            deck: name,
+            took: deck_start.elapsed(),
+        });
+        DeckReport {
+            deck: name.into(),
+            artifacts: exported,
+            failures,
+        }
+    }
+
+    /// Encodes and exports a single artifact.
+    fn deliver(
+        &self,
+        artifact: Artifact<R::Output>,
+        deck: &str,
+        failures: &mut Vec<Failure>,
+    ) -> Option<Artifact<X::Output>> {
+        let meta = artifact.meta();
+
+        let start = Instant::now();
+        let artifact = match self.encoder.export(artifact) {
+            Ok(artifact) => artifact,
+            Err(error) => {
+                self.fail(
+                    failures,
+                    Some(deck),
+                    Some(&meta),
+                    Stage::Encode,
+                    error,
+                    start,
+                );
+                return None;
+            }
+        };
+        self.emit(&Event::ArtifactEncoded {
+            artifact: &meta,
+            took: start.elapsed(),
+            bytes: artifact.data.as_ref().len(),
+        });
+
+        let start = Instant::now();
+        match self.exporter.export(artifact) {
+            Ok(artifact) => {
+                self.emit(&Event::ArtifactExported {
+                    artifact: &meta,
+                    took: start.elapsed(),
+                });
+                Some(artifact)
+            }
+            Err(error) => {
+                self.fail(
+                    failures,
+                    Some(deck),
+                    Some(&meta),
+                    Stage::Export,
+                    error,
+                    start,
+                );
+                None
+            }
+        }
+    }
+
+    fn fail(
+        &self,
+        failures: &mut Vec<Failure>,
+        deck: Option<&str>,
+        artifact: Option<&Artifact<()>>,
+        stage: Stage,
+        error: Error,
+        start: Instant,
+    ) {
+        failures.push(Failure {
+            deck: deck.map(Into::into),
+            artifact: artifact.map(ToString::to_string),
+            stage,
+            error,
+        });
+        self.emit(&Event::Failed {
+            failure: failures.last().expect("was just pushed"),
+            took: start.elapsed(),
+        });
+    }
+
+    fn emit(&self, event: &Event<'_>) {
+        for observer in &self.observers {
+            observer.notify(event);
+        }
+    }
+}
+
+/// The step of a [Pipeline] that an error occurred in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Render,
+    Encode,
+    Export,
+    /// Finishing a deck or the whole run, see [`Export::finish`].
+    Finish,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Render => write!(f, "render"),
+            Stage::Encode => write!(f, "encode"),
+            Stage::Export => write!(f, "export"),
+            Stage::Finish => write!(f, "finish"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    /// The deck that failed, unless it was the run as a whole.
+    pub deck: Option<String>,
+    /// The name of the [Artifact] that failed, if it got far enough to have one.
+    pub artifact: Option<String>,
+    pub stage: Stage,
+    pub error: Error,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.deck, &self.artifact) {
+            (Some(deck), Some(artifact)) => write!(f, "{deck} ({artifact}): ")?,
+            (Some(deck), None) => write!(f, "{deck}: ")?,
+            (None, _) => (),
+        }
+        write!(f, "couldn't {}: {}", self.stage, self.error)
+    }
+}
+
+/// What happened to a single deck during a [`Pipeline::run`].
+#[derive(Debug)]
+pub struct DeckReport<Output> {
+    pub deck: String,
+    pub artifacts: Vec<Artifact<Output>>,
+    pub failures: Vec<Failure>,
+}
+
+impl<Output> DeckReport<Output> {
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// The summary of a [`Pipeline::run`].
+#[derive(Debug)]
+pub struct Report<Output> {
+    pub decks: Vec<DeckReport<Output>>,
+    /// Failures that don't belong to a single deck.
+    pub failures: Vec<Failure>,
+}
+
+impl<Output> Report<Output> {
+    pub fn artifacts(&self) -> impl Iterator<Item = &Artifact<Output>> {
+        self.decks.iter().flat_map(|deck| &deck.artifacts)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Failure> {
+        self.decks
+            .iter()
+            .flat_map(|deck| &deck.failures)
+            .chain(&self.failures)
+    }
+
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty() && self.decks.iter().all(DeckReport::is_success)
+    }
+}
+
+impl<Output> Display for Report<Output> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let failed = self.decks.iter().filter(|deck| !deck.is_success()).count();
+        write!(
+            f,
+            "exported {} artifacts from {} decks, {} decks failed",
+            self.artifacts().count(),
+            self.decks.len(),
+            failed
+        )?;
+        for failure in self.failures() {
+            write!(f, "\n  {failure}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use piet_common::{NullRenderContext, RenderContext};
+
+    use super::*;
+    use crate::{
+        artifact::{Amount, Content},
+        dimensions::Dimensions,
+        Backside, Side, BASE_ASPECT_RATIO,
+    };
+
+    struct TestCard;
+
+    impl CardTrait for TestCard {
+        type Deck = TestDeck;
+
+        fn draw(&self, _: &TestDeck, _: &mut impl RenderContext, _: u32, _: &Dimensions) {}
+
+        fn draw_back(&self, _: &TestDeck, _: &mut impl RenderContext, _: u32, _: &Dimensions) {}
+    }
+
+    struct TestDeck {
+        name: &'static str,
+        cards: Vec<TestCard>,
+    }
+
+    impl DeckTrait<TestCard> for TestDeck {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn cards(&self) -> &[TestCard] {
+            &self.cards
+        }
+
+        fn share_back(&self) -> Backside {
+            Backside::Unique
+        }
+    }
+
+    fn deck(name: &'static str, cards: usize) -> TestDeck {
+        TestDeck {
+            name,
+            cards: (0..cards).map(|_| TestCard).collect(),
+        }
+    }
+
+    /// Draws nothing into images of 16 bytes.
+    #[derive(Clone)]
+    struct TestRenderer;
+
+    impl Render for TestRenderer {
+        type Context<'a> = NullRenderContext;
+        type Output = Vec<u8>;
+
+        fn create_sheet<
+            F: FnOnce(&mut Self::Context<'_>, &Dimensions) -> Result<(), Box<dyn std::error::Error>>,
+        >(
+            &self,
+            draw: F,
+        ) -> Result<Self::Output, Box<dyn std::error::Error>> {
+            draw(
+                &mut NullRenderContext::new(),
+                &Dimensions::new(64, BASE_ASPECT_RATIO),
+            )?;
+            Ok(vec![0; 16])
+        }
+
+        fn create_card<
+            F: FnOnce(&mut Self::Context<'_>, &Dimensions) -> Result<(), Box<dyn std::error::Error>>,
+        >(
+            &self,
+            draw: F,
+        ) -> Result<Self::Output, Box<dyn std::error::Error>> {
+            self.create_sheet(draw)
+        }
+
+        fn downscale(&self, image: &Self::Output, max_side: u32) -> Option<Self::Output> {
+            (image.len() > max_side as usize).then(|| vec![0; max_side as usize])
+        }
+    }
+
+    /// Renders every card on its own. The second card of a deck named `unrenderable` fails.
+    struct OnePerCard;
+
+    impl Layout for OnePerCard {
+        fn build<'a, Format, Deck, Card>(
+            &'a self,
+            deck: &'a Deck,
+            renderer: &'a impl Render<Output = Format>,
+        ) -> impl Iterator<Item = crate::Result<Artifact<Format>>> + 'a
+        where
+            Format: 'a,
+            Deck: DeckTrait<Card>,
+            Card: CardTrait<Deck = Deck> + 'a,
+        {
+            let total = deck.cards().len() as u16;
+            deck.cards().iter().enumerate().map(move |(index, card)| {
+                if deck.name() == "unrenderable" && index == 1 {
+                    return Err("the card is on fire".into());
+                }
+                renderer
+                    .create_card(|ctx, dimensions| {
+                        card.draw(deck, ctx, index as u32, dimensions);
+                        Ok(())
+                    })
+                    .map(|data| Artifact {
+                        deck: deck.name().into(),
+                        shared: deck.share_back(),
+                        data,
+                        side: Side::Front,
+                        content: Content::Single,
+                        amount: Amount::Multiple {
+                            index: index as u16 + 1,
+                            total,
+                        },
+                        aspect_ratio: None,
+                        extension: None,
+                        preview: None,
+                    })
+            })
+        }
+    }
+
+    /// Passes the images through, but fails for decks named `unencodable`.
+    struct TestEncoder;
+
+    impl Export for TestEncoder {
+        type Data = Vec<u8>;
+        type Output = Vec<u8>;
+
+        fn export(&self, artifact: Artifact<Self::Data>) -> crate::Result<Artifact<Self::Output>> {
+            if artifact.deck == "unencodable" {
+                return Err("out of ink".into());
+            }
+            Ok(artifact)
+        }
+    }
+
+    /// Names every artifact and records which hooks were called.
+    #[derive(Default)]
+    struct Recorder {
+        calls: RefCell<Vec<String>>,
+        /// Fail to finish this deck.
+        fail_deck: Option<&'static str>,
+        /// Fail to finish the run.
+        fail_run: bool,
+    }
+
+    impl Export for Recorder {
+        type Data = Vec<u8>;
+        type Output = String;
+
+        fn export(&self, artifact: Artifact<Self::Data>) -> crate::Result<Artifact<Self::Output>> {
+            let name = artifact.to_string();
+            self.calls.borrow_mut().push(format!("export {name}"));
+            Ok(artifact.with_data(name))
+        }
+
+        fn finish_deck(&self, deck: &str) -> crate::Result<()> {
+            self.calls.borrow_mut().push(format!("finish {deck}"));
+            if self.fail_deck == Some(deck) {
+                return Err("the lid is stuck".into());
+            }
+            Ok(())
+        }
+
+        fn finish(&self, complete: bool) -> crate::Result<()> {
+            let run = if complete { "complete" } else { "incomplete" };
+            self.calls.borrow_mut().push(format!("finish {run}"));
+            if self.fail_run {
+                return Err("disk full".into());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failures_are_grouped_by_deck() {
+        let pipeline = Pipeline::new(OnePerCard, TestRenderer, TestEncoder, Recorder::default());
+        let report = pipeline.run(&[
+            deck("fine", 2),
+            deck("unrenderable", 3),
+            deck("unencodable", 1),
+        ]);
+
+        let decks: Vec<_> = report.decks.iter().map(|deck| deck.deck.as_str()).collect();
+        assert_eq!(decks, ["fine", "unrenderable", "unencodable"]);
+        assert!(!report.is_success());
+        assert!(report.failures.is_empty());
+        assert_eq!(report.failures().count(), 2);
+
+        let fine = &report.decks[0];
+        assert!(fine.is_success());
+        assert_eq!(fine.artifacts[1].data, "fine-front-single-2of2");
+
+        // the cards after the one that failed are still rendered
+        let unrenderable = &report.decks[1];
+        assert_eq!(unrenderable.artifacts.len(), 2);
+        let [failure] = &unrenderable.failures[..] else {
+            panic!("{:?}", unrenderable.failures);
+        };
+        assert_eq!(failure.stage, Stage::Render);
+        assert_eq!(failure.deck.as_deref(), Some("unrenderable"));
+        assert_eq!(failure.artifact, None);
+        assert_eq!(
+            failure.to_string(),
+            "unrenderable: couldn't render: the card is on fire"
+        );
+
+        let unencodable = &report.decks[2];
+        assert!(unencodable.artifacts.is_empty());
+        assert_eq!(unencodable.failures[0].stage, Stage::Encode);
+        assert_eq!(
+            unencodable.failures[0].to_string(),
+            "unencodable (unencodable-front-single-1of1): couldn't encode: out of ink"
+        );
+
+        assert_eq!(
+            report.to_string().lines().next(),
+            Some("exported 4 artifacts from 3 decks, 2 decks failed")
+        );
+    }
+
+    #[test]
+    fn finishing() {
+        let exporter = Recorder {
+            fail_deck: Some("b"),
+            fail_run: true,
+            ..Default::default()
+        };
+        let pipeline = Pipeline::new(OnePerCard, TestRenderer, TestEncoder, exporter);
+        let report = pipeline.run(&[deck("a", 1), deck("b", 0)]);
+
+        assert_eq!(
+            *pipeline.exporter.calls.borrow(),
+            [
+                "export a-front-single-1of1",
+                "finish a",
+                "finish b",
+                "finish incomplete"
+            ]
+        );
+
+        assert!(report.decks[0].is_success());
+        let failure = &report.decks[1].failures[0];
+        assert_eq!(failure.stage, Stage::Finish);
+        assert_eq!(failure.to_string(), "b: couldn't finish: the lid is stuck");
+
+        // failing to finish the run doesn't belong to any deck
+        let [failure] = &report.failures[..] else {
+            panic!("{:?}", report.failures);
+        };
+        assert_eq!(failure.stage, Stage::Finish);
+        assert_eq!(failure.deck, None);
+        assert_eq!(failure.to_string(), "couldn't finish: disk full");
+
+        let pipeline = Pipeline::new(OnePerCard, TestRenderer, TestEncoder, Recorder::default());
+        assert!(pipeline.run(&[deck("a", 1)]).is_success());
+        assert_eq!(
+            pipeline.exporter.calls.borrow().last().unwrap(),
+            "finish complete"
+        );
+    }
+
+    /// A line per event, without the durations.
+    fn describe(event: &Event<'_>) -> String {
+        match event {
+            Event::RunStarted { decks } => format!("run {decks}"),
+            Event::DeckStarted { deck, index } => format!("deck {index} {deck}"),
+            Event::SheetRendered { artifact, .. } => format!("rendered {artifact}"),
+            Event::PreviewScaled { artifact, .. } => format!("scaled {artifact}"),
+            Event::ArtifactEncoded {
+                artifact, bytes, ..
+            } => format!("encoded {artifact} {bytes}"),
+            Event::ArtifactExported { artifact, .. } => format!("exported {artifact}"),
+            Event::Failed { failure, .. } => format!("failed {failure}"),
+            Event::DeckFinished { deck, .. } => format!("finished {deck}"),
+            Event::RunFinished { .. } => "done".into(),
+        }
+    }
+
+    #[test]
+    fn events() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = events.clone();
+        let pipeline = Pipeline::new(OnePerCard, TestRenderer, TestEncoder, Recorder::default())
+            // the images are 16 bytes, so there's no preview of 64
+            .with_previews([4, 64])
+            .observe(move |event: &Event<'_>| recorded.borrow_mut().push(describe(event)));
+        pipeline.run(&[deck("a", 1), deck("unencodable", 1)]);
+
+        assert_eq!(
+            *events.borrow(),
+            [
+                "run 2",
+                "deck 0 a",
+                "rendered a-front-single-1of1",
+                "scaled a-front-single-1of1-4px",
+                "encoded a-front-single-1of1 16",
+                "exported a-front-single-1of1",
+                "encoded a-front-single-1of1-4px 4",
+                "exported a-front-single-1of1-4px",
+                "finished a",
+                "deck 1 unencodable",
+                "rendered unencodable-front-single-1of1",
+                "scaled unencodable-front-single-1of1-4px",
+                "failed unencodable (unencodable-front-single-1of1): couldn't encode: out of ink",
+                "failed unencodable (unencodable-front-single-1of1-4px): couldn't encode: out of ink",
+                "finished unencodable",
+                "done",
+            ]
+        );
+    }
+}
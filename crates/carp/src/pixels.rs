@@ -0,0 +1,171 @@
+//! Conversions of rendered [ImageBuf]s into the pixel layouts that encoders expect.
+//!
+//! [`ImageRenderer`](crate::renderer::ImageRenderer) produces premultiplied RGBA,
+//! but most image formats store straight alpha or no alpha at all.
+//! Previews are shrunk here as well, see [downscale].
+
+use std::borrow::Cow;
+
+use piet_common::{Color, ImageBuf, ImageFormat};
+
+/// The pixels of `image` as RGBA with straight (not premultiplied) alpha, 4 bytes per pixel.
+#[must_use]
+pub fn rgba(image: &ImageBuf) -> Cow<'_, [u8]> {
+    let pixels = image.raw_pixels();
+    match image.format() {
+        ImageFormat::Grayscale => pixels.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+        ImageFormat::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        ImageFormat::RgbaPremul => pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let a = p[3];
+                [divide(p[0], a), divide(p[1], a), divide(p[2], a), a]
+            })
+            .collect(),
+        _ => Cow::Borrowed(pixels),
+    }
+}
+
+/// The pixels of `image` as RGB, composited onto an opaque `background`, 3 bytes per pixel.
+#[must_use]
+pub fn rgb(image: &ImageBuf, background: Color) -> Vec<u8> {
+    let (r, g, b, _) = background.as_rgba8();
+    let background = [r, g, b];
+    let pixels = image.raw_pixels();
+    match image.format() {
+        ImageFormat::Grayscale => pixels.iter().flat_map(|&v| [v, v, v]).collect(),
+        ImageFormat::Rgb => pixels.to_vec(),
+        ImageFormat::RgbaPremul => pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let rest = 255 - p[3];
+                [0, 1, 2].map(|i| p[i].saturating_add(multiply(background[i], rest)))
+            })
+            .collect(),
+        _ => pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let rest = 255 - p[3];
+                [0, 1, 2]
+                    .map(|i| multiply(p[i], p[3]).saturating_add(multiply(background[i], rest)))
+            })
+            .collect(),
+    }
+}
+
+/// Shrinks `image` so its longer side is at most `max_side` pixels, keeping its aspect ratio and format.
+///
+/// Every pixel of the result is the average of the pixels it covers, which is cheap
+/// and doesn't alias the thin lines of text the way skipping pixels would.
+/// Premultiplied alpha can be averaged as is, transparent pixels don't bleed their color.
+///
+/// Returns [None] if the image is small enough already.
+#[must_use]
+pub fn downscale(image: &ImageBuf, max_side: u32) -> Option<ImageBuf> {
+    let (width, height) = (image.width(), image.height());
+    let max_side = max_side.max(1) as usize;
+    if width.max(height) <= max_side {
+        return None;
+    }
+    let scale = max_side as f64 / width.max(height) as f64;
+    let new_width = ((width as f64 * scale).round() as usize).max(1);
+    let new_height = ((height as f64 * scale).round() as usize).max(1);
+
+    let bytes = image.format().bytes_per_pixel();
+    let pixels = image.raw_pixels();
+    let mut scaled = Vec::with_capacity(new_width * new_height * bytes);
+    let mut sums = vec![0_u64; bytes];
+    for y in 0..new_height {
+        let rows = y * height / new_height
+            ..((y + 1) * height / new_height).max(y * height / new_height + 1);
+        for x in 0..new_width {
+            let columns =
+                x * width / new_width..((x + 1) * width / new_width).max(x * width / new_width + 1);
+            sums.fill(0);
+            for row in rows.clone() {
+                let start = (row * width + columns.start) * bytes;
+                let end = (row * width + columns.end) * bytes;
+                for pixel in pixels[start..end].chunks_exact(bytes) {
+                    for (sum, &value) in sums.iter_mut().zip(pixel) {
+                        *sum += u64::from(value);
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len()) as u64;
+            scaled.extend(sums.iter().map(|sum| ((sum + count / 2) / count) as u8));
+        }
+    }
+    Some(ImageBuf::from_raw(
+        scaled,
+        image.format(),
+        new_width,
+        new_height,
+    ))
+}
+
+/// `a * b / 255`, rounded.
+fn multiply(a: u8, b: u8) -> u8 {
+    ((u16::from(a) * u16::from(b) + 127) / 255) as u8
+}
+
+/// Undoes [multiply] for a premultiplied `color` with alpha `a`.
+fn divide(color: u8, a: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    ((u16::from(color) * 255 + u16::from(a) / 2) / u16::from(a)).min(255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(pixels: &[u8], format: ImageFormat) -> ImageBuf {
+        let width = pixels.len() / format.bytes_per_pixel();
+        ImageBuf::from_raw(pixels, format, width, 1)
+    }
+
+    #[test]
+    fn unpremultiply() {
+        let premultiplied = image(
+            &[128, 64, 0, 128, 0, 0, 0, 0, 10, 20, 30, 255],
+            ImageFormat::RgbaPremul,
+        );
+
+        assert_eq!(
+            *rgba(&premultiplied),
+            [255, 128, 0, 128, 0, 0, 0, 0, 10, 20, 30, 255]
+        );
+    }
+
+    #[test]
+    fn downscaling() {
+        let image = ImageBuf::from_raw(
+            [0, 100, 200, 255, 10, 20, 30, 40].as_slice(),
+            ImageFormat::Grayscale,
+            4,
+            2,
+        );
+
+        let scaled = downscale(&image, 2).unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (2, 1));
+        assert_eq!(scaled.raw_pixels(), [33, 131]);
+        assert!(downscale(&image, 4).is_none());
+    }
+
+    #[test]
+    fn flatten() {
+        let premultiplied = image(&[128, 0, 0, 128, 0, 0, 0, 0], ImageFormat::RgbaPremul);
+        let separate = image(&[255, 0, 0, 128, 0, 0, 0, 0], ImageFormat::RgbaSeparate);
+
+        assert_eq!(
+            rgb(&premultiplied, Color::WHITE),
+            [255, 127, 127, 255, 255, 255]
+        );
+        assert_eq!(rgb(&separate, Color::WHITE), [255, 127, 127, 255, 255, 255]);
+        assert_eq!(rgb(&separate, Color::BLACK), [128, 0, 0, 0, 0, 0]);
+    }
+}
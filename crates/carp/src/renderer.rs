@@ -3,7 +3,29 @@ use log::trace;
 use piet_common::D2DRenderContext;
 use piet_common::ImageBuf;
 use piet_common::RenderContext;
-use std::error::Error;
+use serde::Serialize;
+use std::{error::Error, sync::Mutex, time::Duration};
+
+/// Where a single render spent its time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Timings {
+    /// Waiting for a free device from the [Pool].
+    #[serde(serialize_with = "crate::metrics::seconds")]
+    pub wait: Duration,
+    /// Running the draw calls.
+    #[serde(serialize_with = "crate::metrics::seconds")]
+    pub draw: Duration,
+    /// Copying the finished bitmap into memory.
+    #[serde(serialize_with = "crate::metrics::seconds")]
+    pub copy: Duration,
+}
+
+impl Timings {
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.wait + self.draw + self.copy
+    }
+}
 
 pub trait Render: Clone {
     type Context<'a>: RenderContext;
@@ -16,11 +38,27 @@ pub trait Render: Clone {
         &self,
         draw: F,
     ) -> Result<Self::Output, Box<dyn Error>>;
+
+    /// The [Timings] of the last sheet or card this renderer created, if it measures them.
+    ///
+    /// [None] if that failed, so a failed render never reports the timings of an earlier one.
+    fn last_timings(&self) -> Option<Timings> {
+        None
+    }
+
+    /// A copy of `image` whose longer side is at most `max_side` pixels, for previews.
+    ///
+    /// [None] if the image is small enough already or the renderer can't scale its output.
+    fn downscale(&self, _image: &Self::Output, _max_side: u32) -> Option<Self::Output> {
+        None
+    }
 }
 
 pub struct ImageRenderer<T: RenderContext> {
     device_pool: Pool,
     dimensions: Dimensions,
+    /// See [`Render::last_timings`], every clone measures its own renders.
+    last_timings: Mutex<Option<Timings>>,
     __marker: std::marker::PhantomData<T>,
 }
 
@@ -29,6 +67,7 @@ impl<T: RenderContext> Clone for ImageRenderer<T> {
         Self {
             device_pool: self.device_pool.clone(),
             dimensions: self.dimensions,
+            last_timings: Mutex::default(),
             __marker: std::marker::PhantomData,
         }
     }
@@ -39,6 +78,7 @@ impl<T: RenderContext> ImageRenderer<T> {
         Self {
             device_pool: Pool::default(),
             dimensions,
+            last_timings: Mutex::default(),
             __marker: std::marker::PhantomData,
         }
     }
@@ -56,6 +96,7 @@ impl Render for ImageRenderer<D2DRenderContext<'_>> {
         draw: F,
     ) -> Result<Self::Output, Box<dyn Error>> {
         let trace_function_start = std::time::Instant::now();
+        *self.last_timings.lock().map_err(|e| e.to_string())? = None;
         let mut device = self.device_pool.get()?;
         let mut bitmap = device.bitmap_target(
             self.dimensions.width as usize,
@@ -67,31 +108,19 @@ impl Render for ImageRenderer<D2DRenderContext<'_>> {
 
         let trace_draw_start = std::time::Instant::now();
         draw(&mut ctx, &self.dimensions)?;
-        let took = trace_draw_start.elapsed();
         ctx.finish()?;
         drop(ctx);
 
         let trace_convert_start = std::time::Instant::now();
         let image = bitmap.to_image_buf(piet_common::ImageFormat::RgbaPremul)?;
 
-        let trace_function_end = std::time::Instant::now();
-        let trace_full_d = trace_function_end.duration_since(trace_function_start);
-        let trace_full_df32 = trace_full_d.as_secs_f32();
-        trace!(
-            "Rendered sheet in {:?}: {:.0}% wait for device, {:.0}% draw, {:.0}% copy",
-            trace_full_d,
-            trace_draw_start
-                .duration_since(trace_function_start)
-                .as_secs_f32()
-                / trace_full_df32
-                * 100.0,
-            took.as_secs_f32() / trace_full_df32 * 100.0,
-            trace_function_end
-                .duration_since(trace_convert_start)
-                .as_secs_f32()
-                / trace_full_df32
-                * 100.0,
-        );
+        let timings = Timings {
+            wait: trace_draw_start.duration_since(trace_function_start),
+            draw: trace_convert_start.duration_since(trace_draw_start),
+            copy: trace_convert_start.elapsed(),
+        };
+        trace_timings("sheet", &timings);
+        *self.last_timings.lock().map_err(|e| e.to_string())? = Some(timings);
         Ok(image)
     }
 
@@ -100,6 +129,7 @@ impl Render for ImageRenderer<D2DRenderContext<'_>> {
         draw: F,
     ) -> Result<Self::Output, Box<dyn Error>> {
         let trace_function_start = std::time::Instant::now();
+        *self.last_timings.lock().map_err(|e| e.to_string())? = None;
         let mut device = self.device_pool.get()?;
         let mut bitmap = device.bitmap_target(
             self.dimensions.card.width as usize,
@@ -111,31 +141,39 @@ impl Render for ImageRenderer<D2DRenderContext<'_>> {
 
         let trace_draw_start = std::time::Instant::now();
         draw(&mut ctx, &self.dimensions)?;
-        let took = trace_draw_start.elapsed();
         ctx.finish()?;
         drop(ctx);
 
         let trace_convert_start = std::time::Instant::now();
         let image = bitmap.to_image_buf(piet_common::ImageFormat::RgbaPremul)?;
 
-        let trace_function_end = std::time::Instant::now();
-        let trace_full_d = trace_function_end.duration_since(trace_function_start);
-        let trace_full_df32 = trace_full_d.as_secs_f32();
-        trace!(
-            "Rendered card in {:?}: {:.0}% wait for device, {:.0}% draw, {:.0}% copy",
-            trace_full_d,
-            trace_draw_start
-                .duration_since(trace_function_start)
-                .as_secs_f32()
-                / trace_full_df32
-                * 100.0,
-            took.as_secs_f32() / trace_full_df32 * 100.0,
-            trace_function_end
-                .duration_since(trace_convert_start)
-                .as_secs_f32()
-                / trace_full_df32
-                * 100.0,
-        );
+        let timings = Timings {
+            wait: trace_draw_start.duration_since(trace_function_start),
+            draw: trace_convert_start.duration_since(trace_draw_start),
+            copy: trace_convert_start.elapsed(),
+        };
+        trace_timings("card", &timings);
+        *self.last_timings.lock().map_err(|e| e.to_string())? = Some(timings);
         Ok(image)
     }
+
+    fn last_timings(&self) -> Option<Timings> {
+        self.last_timings.lock().ok().and_then(|last| *last)
+    }
+
+    fn downscale(&self, image: &Self::Output, max_side: u32) -> Option<Self::Output> {
+        crate::pixels::downscale(image, max_side)
+    }
+}
+
+fn trace_timings(what: &str, timings: &Timings) {
+    let total = timings.total();
+    let percent = |part: Duration| part.as_secs_f32() / total.as_secs_f32() * 100.0;
+    trace!(
+        "Rendered {what} in {:?}: {:.0}% wait for device, {:.0}% draw, {:.0}% copy",
+        total,
+        percent(timings.wait),
+        percent(timings.draw),
+        percent(timings.copy),
+    );
 }
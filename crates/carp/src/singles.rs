@@ -0,0 +1,136 @@
+use piet_common::kurbo::{Affine, Vec2};
+use piet_common::RenderContext;
+
+use crate::card::Side;
+use crate::deck::Backside;
+use crate::{
+    artifact::{Amount, Artifact, Content},
+    dimensions::Dimensions,
+    layout::Layout,
+    renderer::Render,
+    Card as CardTrait, Deck as DeckTrait, Result,
+};
+
+/// Lays a deck out the way print-on-demand services expect it:
+/// every front and back as an image of its own, numbered by the index of the card.
+///
+/// A shared back is only rendered once.
+/// Use [`Dimensions::print`] to render at the resolution and with the bleed the service asks for.
+pub struct Singles;
+
+impl Layout for Singles {
+    fn build<'a, Format, Deck, Card>(
+        &'a self,
+        deck: &'a Deck,
+        renderer: &'a impl Render<Output = Format>,
+    ) -> impl Iterator<Item = Result<Artifact<Format>>> + 'a
+    where
+        Format: 'a,
+        Deck: DeckTrait<Card>,
+        Card: CardTrait<Deck = Deck> + 'a,
+    {
+        let total = deck.cards().len() as u16;
+        let amount = move |index: usize| Amount::Multiple {
+            index: index as u16 + 1,
+            total,
+        };
+
+        let fronts = deck.cards().iter().enumerate().map(move |(index, card)| {
+            Singles::render_card(
+                renderer,
+                deck,
+                Side::Front,
+                amount(index),
+                |ctx, dimensions| {
+                    card.draw(deck, ctx, index as u32, dimensions);
+                },
+            )
+        });
+
+        let backs = if deck.share_back() == Backside::Shared {
+            Box::new(deck.cards().first().into_iter().map(move |card| {
+                Singles::render_card(
+                    renderer,
+                    deck,
+                    Side::Back,
+                    Amount::Single,
+                    |ctx, dimensions| {
+                        card.draw_back(deck, ctx, 0, dimensions);
+                    },
+                )
+            })) as Box<dyn Iterator<Item = _>>
+        } else {
+            Box::new(deck.cards().iter().enumerate().map(move |(index, card)| {
+                Singles::render_card(
+                    renderer,
+                    deck,
+                    Side::Back,
+                    amount(index),
+                    |ctx, dimensions| {
+                        card.draw_back(deck, ctx, index as u32, dimensions);
+                    },
+                )
+            }))
+        };
+
+        fronts.chain(backs)
+    }
+}
+
+impl Singles {
+    /// Renders a card with `draw`, filling the bleed with an enlarged copy of the card,
+    /// so the colors at its edges run past where it gets cut.
+    ///
+    /// The card is rendered as a sheet of its own, as [`Dimensions::print`] sizes the whole image including the bleed.
+    fn render_card<R, Deck, Card>(
+        renderer: &R,
+        deck: &Deck,
+        side: Side,
+        amount: Amount,
+        draw: impl Fn(&mut R::Context<'_>, &Dimensions),
+    ) -> Result<Artifact<R::Output>>
+    where
+        R: Render,
+        Deck: DeckTrait<Card>,
+        Card: CardTrait<Deck = Deck>,
+    {
+        renderer
+            .create_sheet(|ctx, dimensions| {
+                let bleed = dimensions.bleed;
+                let card = dimensions.card;
+                if bleed > 0. {
+                    let scale = f64::max(
+                        (card.width + 2. * bleed) / card.width,
+                        (card.height + 2. * bleed) / card.height,
+                    );
+                    let center = Vec2::new(card.width / 2. + bleed, card.height / 2. + bleed);
+                    ctx.with_save(|ctx| {
+                        ctx.transform(
+                            Affine::translate(center)
+                                * Affine::scale(scale)
+                                * Affine::translate(-card.to_vec2() / 2.),
+                        );
+                        draw(ctx, dimensions);
+                        Ok(())
+                    })?;
+                }
+                ctx.with_save(|ctx| {
+                    ctx.transform(Affine::translate((bleed, bleed)));
+                    draw(ctx, dimensions);
+                    Ok(())
+                })?;
+                Ok(())
+            })
+            .map(|image| Artifact {
+                deck: deck.name().into(),
+                data: image,
+                side,
+                shared: deck.share_back(),
+                content: Content::Single,
+                amount,
+                aspect_ratio: None,
+                extension: None,
+                preview: None,
+            })
+    }
+}
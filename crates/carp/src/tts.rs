@@ -6,14 +6,18 @@ use crate::deck::Backside;
 use crate::{
     artifact::{Amount, Artifact, Content},
     dimensions::Dimensions,
+    layout::Layout,
     renderer::Render,
     Card as CardTrait, Deck as DeckTrait, Result, COLUMNS, ROWS,
 };
 
+/// Lays a deck out the way the Tabletop Simulator expects it:
+/// fronts in sheets of [ROWS] x [COLUMNS] cards, backs either as a single card or as matching sheets.
 pub struct TTS;
 
-impl TTS {
-    pub fn build<'a, Format, Deck, Card>(
+impl Layout for TTS {
+    fn build<'a, Format, Deck, Card>(
+        &'a self,
         deck: &'a Deck,
         renderer: &'a impl Render<Output = Format>,
     ) -> impl Iterator<Item = Result<Artifact<Format>>> + 'a
@@ -43,6 +47,7 @@ impl TTS {
                         amount: Amount::Single,
                         aspect_ratio: None,
                         extension: None,
+                        preview: None,
                     })
             }))
         } else {
@@ -51,7 +56,9 @@ impl TTS {
 
         front.chain(back)
     }
+}
 
+impl TTS {
     fn render_sheet<'a, Format, Deck, Card>(
         renderer: &'a impl Render<Output = Format>,
         deck: &'a Deck,
@@ -99,6 +106,7 @@ impl TTS {
                             }
                         },
                         extension: Default::default(),
+                        preview: Default::default(),
                     })
             })
     }
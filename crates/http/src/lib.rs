@@ -0,0 +1,264 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use attohttpc::{header::CONTENT_TYPE, Method, RequestBuilder, Response, StatusCode};
+use carp::{
+    artifact::Artifact,
+    export::{content_type, encode, Export, Store, Template},
+};
+use log::debug;
+
+/// Uploads every artifact with an HTTP `PUT` to a web server or a WebDAV share like Nextcloud.
+///
+/// The output of every artifact is the URL it can be downloaded from,
+/// so the server has to serve the uploads to whoever loads the deck.
+pub struct HttpExporter {
+    /// The URL the files are uploaded below, e.g. `https://cloud.example.com/remote.php/dav/files/me/decks/`.
+    pub url: String,
+    /// The URL the uploaded files are served below, if it isn't [`HttpExporter::url`],
+    /// e.g. a public directory of the web server that the WebDAV share points into.
+    pub public_url: Option<String>,
+    /// The username for basic authentication.
+    pub username: Option<String>,
+    /// The password for basic authentication, Nextcloud wants an app password here.
+    pub password: Option<String>,
+    /// How the files are named. Slashes create collections.
+    pub template: Template,
+    /// Create the collections a file is uploaded into with `MKCOL` before the first upload into them.
+    ///
+    /// Otherwise they're only created once an upload fails with 409 Conflict,
+    /// which is how WebDAV servers answer a `PUT` into a missing collection.
+    pub webdav: bool,
+    /// How long a single request may take.
+    pub timeout: Duration,
+    /// The collections that were created or found to exist already.
+    collections: Mutex<HashSet<String>>,
+}
+
+impl HttpExporter {
+    /// Uploads below `url`, which is treated as a directory even without a trailing slash.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: directory(url.into()),
+            public_url: None,
+            username: None,
+            password: None,
+            template: Template::default(),
+            webdav: false,
+            timeout: Duration::from_secs(60),
+            collections: Mutex::default(),
+        }
+    }
+
+    /// A request to `url` with the timeout and the credentials.
+    fn request(&self, method: Method, url: &str) -> Result<RequestBuilder, Box<dyn Error>> {
+        let request = RequestBuilder::try_new(method, url)
+            .map_err(|e| format!("invalid URL {url}: {e}"))?
+            .timeout(self.timeout);
+        Ok(match self.username {
+            Some(ref username) => request.basic_auth(username, self.password.as_ref()),
+            None => request,
+        })
+    }
+
+    /// Creates the collections `name` is uploaded into, unless they exist already.
+    fn create_collections(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        // held for the whole loop, so decks that share a collection don't both try to create it
+        let mut collections = self.collections.lock().map_err(|e| e.to_string())?;
+        for (index, _) in name.match_indices('/') {
+            let collection = &name[..=index];
+            if collections.contains(collection) {
+                continue;
+            }
+
+            let url = format!("{}{}", self.url, encode(collection));
+            let response = self.request(Method::from_bytes(b"MKCOL")?, &url)?.send()?;
+            match response.status().as_u16() {
+                200..=299 => debug!("created the collection {url}"),
+                // the collection exists already
+                405 => (),
+                _ => Err(failure(&url, "couldn't create the collection", response))?,
+            }
+            collections.insert(collection.into());
+        }
+        Ok(())
+    }
+
+    /// Uploads `data` as `name` and returns the URL it's served from.
+    fn put(&self, name: &str, data: &[u8], content_type: &str) -> Result<PathBuf, Box<dyn Error>> {
+        if self.webdav {
+            self.create_collections(name)?;
+        }
+
+        let url = format!("{}{}", self.url, encode(name));
+        let upload = || -> Result<Response, Box<dyn Error>> {
+            Ok(self
+                .request(Method::PUT, &url)?
+                .header(CONTENT_TYPE, content_type)
+                .bytes(data)
+                .send()?)
+        };
+        let mut response = upload()?;
+        if response.status() == StatusCode::CONFLICT && !self.webdav && name.contains('/') {
+            debug!("the collection of {url} is missing, creating it");
+            self.create_collections(name)?;
+            response = upload()?;
+        }
+        if !response.is_success() {
+            Err(failure(&url, "couldn't upload to", response))?;
+        }
+        debug!("uploaded {url}");
+
+        let base = match self.public_url {
+            Some(ref public_url) => directory(public_url.clone()),
+            None => self.url.clone(),
+        };
+        Ok(PathBuf::from(format!("{base}{}", encode(name))))
+    }
+}
+
+impl Export for HttpExporter {
+    type Data = Vec<u8>;
+    type Output = PathBuf;
+
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        let name = self.template.render(&artifact);
+        let content_type = content_type(artifact.extension.as_deref().unwrap_or_default());
+        let location = self.put(&name, &artifact.data, content_type)?;
+        Ok(artifact.with_data(location))
+    }
+}
+
+impl Store for HttpExporter {
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        let extension = Path::new(name).extension().unwrap_or_default();
+        self.put(name, data, content_type(&extension.to_string_lossy()))
+    }
+}
+
+/// Appends the slash that makes `url` a directory to resolve names against.
+fn directory(mut url: String) -> String {
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    url
+}
+
+/// Describes a request to `url` that failed with `response`, including the start of its body.
+fn failure(url: &str, action: &str, response: Response) -> String {
+    let status = response.status().as_u16();
+    let hint = match status {
+        401 | 403 => ", check the username and password",
+        404 | 409 => ", does the collection exist?",
+        _ => "",
+    };
+    let body = response.text().unwrap_or_default();
+    let body: String = body.trim().chars().take(200).collect();
+    if body.is_empty() {
+        format!("{action} {url} (HTTP {status}){hint}")
+    } else {
+        format!("{action} {url} (HTTP {status}){hint}: {body}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{Ipv4Addr, TcpListener},
+        thread::{self, JoinHandle},
+    };
+
+    use super::*;
+
+    /// Answers one request per status in `statuses` and returns the method and path of each request.
+    fn stub(statuses: &'static [(u16, &'static str)]) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let url = format!("http://{}/dav", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for &(status, body) in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    match header.split_once(':') {
+                        Some((name, value)) if name.eq_ignore_ascii_case("content-length") => {
+                            length = value.trim().parse().unwrap();
+                        }
+                        Some(_) => (),
+                        None => break,
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                requests.push(request.rsplit_once(' ').unwrap().0.to_string());
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Stub\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn collections_are_created_on_conflict() {
+        let (url, server) = stub(&[(409, ""), (201, ""), (201, ""), (201, "")]);
+        let exporter = HttpExporter::new(&url);
+        assert_eq!(
+            exporter.store("Base Game/front.png", b"front").unwrap(),
+            PathBuf::from(format!("{url}/Base%20Game/front.png"))
+        );
+        exporter.store("Base Game/back.png", b"back").unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            [
+                "PUT /dav/Base%20Game/front.png",
+                "MKCOL /dav/Base%20Game/",
+                "PUT /dav/Base%20Game/front.png",
+                "PUT /dav/Base%20Game/back.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_include_the_body() {
+        let (url, server) = stub(&[(403, "<s:message>Quota exceeded</s:message>\n")]);
+        let error = HttpExporter::new(&url)
+            .store("front.png", b"front")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "couldn't upload to {url}/front.png (HTTP 403), check the username and password: \
+                <s:message>Quota exceeded</s:message>"
+            )
+        );
+        assert_eq!(server.join().unwrap(), ["PUT /dav/front.png"]);
+    }
+
+    #[test]
+    fn directories() {
+        assert_eq!(
+            directory("http://localhost/dav".into()),
+            "http://localhost/dav/"
+        );
+    }
+}
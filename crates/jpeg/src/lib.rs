@@ -0,0 +1,140 @@
+use std::error::Error;
+
+use carp::{
+    artifact::Artifact,
+    dimensions::AspectRatio,
+    export::Export,
+    piet_common::{Color, ImageBuf},
+    pixels,
+};
+use jpeg_encoder::{ColorType, Encoder};
+use log::trace;
+
+/// Encodes rendered images as (lossy) JPEG files.
+///
+/// JPEG has no transparency, so the images are flattened onto [`JPEGExporter::background`].
+pub struct JPEGExporter {
+    /// From 1 (smallest files) to 100 (best quality).
+    pub quality: u8,
+    /// The color that shines through transparent parts of the image.
+    pub background: Color,
+}
+
+impl JPEGExporter {
+    #[must_use]
+    pub fn new(quality: u8) -> Self {
+        Self {
+            quality,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for JPEGExporter {
+    fn default() -> Self {
+        Self {
+            quality: 90,
+            background: Color::WHITE,
+        }
+    }
+}
+
+impl Export for JPEGExporter {
+    type Data = ImageBuf;
+    type Output = Vec<u8>;
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        let start = std::time::Instant::now();
+
+        let (image, artifact) = artifact.extract_data();
+        let aspect_ratio = Some(AspectRatio::new(
+            image.width() as f64,
+            image.height() as f64,
+        ));
+
+        let width = u16::try_from(image.width())
+            .map_err(|_| format!("{} pixels are too wide for a JPEG", image.width()))?;
+        let height = u16::try_from(image.height())
+            .map_err(|_| format!("{} pixels are too high for a JPEG", image.height()))?;
+
+        let mut buf = Vec::new();
+        let encoder = Encoder::new(&mut buf, self.quality);
+        encoder.encode(
+            &pixels::rgb(&image, self.background),
+            width,
+            height,
+            ColorType::Rgb,
+        )?;
+
+        trace!(
+            "Exported {:?}.jpg in {:?}",
+            artifact.to_string(),
+            start.elapsed()
+        );
+
+        Ok(Artifact {
+            aspect_ratio,
+            extension: Some("jpg".into()),
+            ..artifact.with_data(buf)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use carp::{
+        artifact::{Amount, Content},
+        piet_common::ImageFormat,
+        Backside, Side,
+    };
+    use jpeg_decoder::{Decoder, PixelFormat};
+
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        // opaque red on the left, transparent on the right
+        let pixels: Vec<u8> = (0..8 * 16)
+            .flat_map(|index| {
+                if index % 16 < 8 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 0, 0]
+                }
+            })
+            .collect();
+        let artifact = Artifact {
+            deck: "deck".into(),
+            shared: Backside::Shared,
+            data: ImageBuf::from_raw(pixels, ImageFormat::RgbaPremul, 16, 8),
+            side: Side::Front,
+            content: Content::Single,
+            amount: Amount::Single,
+            aspect_ratio: None,
+            extension: None,
+            preview: None,
+        };
+
+        let exported = JPEGExporter::new(95).export(artifact).unwrap();
+        assert_eq!(exported.extension.as_deref(), Some("jpg"));
+        assert_eq!(exported.aspect_ratio, Some(AspectRatio(2.0)));
+
+        let mut decoder = Decoder::new(exported.data.as_slice());
+        let image = decoder.decode().unwrap();
+        let info = decoder.info().unwrap();
+        assert_eq!((info.width, info.height), (16, 8));
+        assert_eq!(info.pixel_format, PixelFormat::RGB24);
+
+        let pixel = |x: usize| &image[x * 3..x * 3 + 3];
+        let red = pixel(0);
+        assert!(red[0] > 230 && red[1] < 24 && red[2] < 24, "{red:?}");
+        // the transparent half is flattened onto the white background
+        let background = pixel(15);
+        assert!(
+            background.iter().all(|&channel| channel > 230),
+            "{background:?}"
+        );
+    }
+}
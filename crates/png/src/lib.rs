@@ -1,10 +1,113 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    io::{self, Write},
+};
 
-use carp::{export::Export, artifact::Artifact, dimensions::AspectRatio, piet_common::ImageBuf};
+use carp::{
+    artifact::{Amount, Artifact, Content},
+    dimensions::AspectRatio,
+    export::Export,
+    hash::content_hash,
+    piet_common::{Color, ImageBuf},
+    pixels,
+};
 use log::trace;
-use mtpng::{Header, encoder::{Options, Encoder}, ColorType};
+use mtpng::{
+    encoder::{Encoder, Options},
+    ColorType, Header,
+};
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
-pub struct PNGExporter;
+pub use mtpng::{CompressionLevel, Filter, Mode, Strategy};
+
+/// What happens to transparent parts of an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alpha {
+    /// Write RGBA pixels.
+    Keep,
+    /// Composite the image onto an opaque color and write RGB pixels, which makes for smaller files.
+    Flatten(Color),
+}
+
+/// Encodes rendered images as PNG files.
+///
+/// The default settings keep transparency and let `mtpng` choose filters and strategies,
+/// spreading the work over all cores.
+pub struct PNGExporter {
+    pub alpha: Alpha,
+    /// Higher levels make smaller files, but take longer to encode.
+    pub compression: CompressionLevel,
+    pub filter: Mode<Filter>,
+    pub strategy: Mode<Strategy>,
+    /// Whether to write text chunks describing where the image came from, see [`PNGExporter::text`].
+    pub metadata: bool,
+    /// The name and version of the program that made the image, for the `Software` text chunk.
+    pub generator: String,
+    thread_pool: Option<ThreadPool>,
+}
+
+impl PNGExporter {
+    #[must_use]
+    pub fn new(alpha: Alpha) -> Self {
+        Self {
+            alpha,
+            ..Self::default()
+        }
+    }
+
+    /// Encodes on a pool of `threads` threads instead of the global one, which uses every core.
+    pub fn with_threads(mut self, threads: usize) -> Result<Self, Box<dyn Error>> {
+        self.thread_pool = Some(ThreadPoolBuilder::new().num_threads(threads).build()?);
+        Ok(self)
+    }
+
+    /// The text chunks written into the file, so a stray sheet can be traced back to its deck.
+    ///
+    /// `Source` is the [`content_hash`] of the rendered pixels,
+    /// which stays the same as long as the cards look the same, no matter how they're encoded.
+    #[must_use]
+    pub fn text<Format>(
+        &self,
+        artifact: &Artifact<Format>,
+        pixels: &[u8],
+    ) -> Vec<(&'static str, String)> {
+        let (grid, cards) = match artifact.content {
+            Content::Single => ("1x1".to_string(), 1),
+            Content::Sheet {
+                rows,
+                columns,
+                total,
+            } => (format!("{rows}x{columns}"), total),
+        };
+        let page = match artifact.amount {
+            Amount::Single => "1/1".to_string(),
+            Amount::Multiple { index, total } => format!("{index}/{total}"),
+        };
+        vec![
+            ("Title", artifact.deck.clone()),
+            ("Side", artifact.side.to_string()),
+            ("Grid", grid),
+            ("Cards", cards.to_string()),
+            ("Page", page),
+            ("Software", self.generator.clone()),
+            ("Source", content_hash(pixels)),
+        ]
+    }
+}
+
+impl Default for PNGExporter {
+    fn default() -> Self {
+        Self {
+            alpha: Alpha::Keep,
+            compression: CompressionLevel::Default,
+            filter: Mode::Adaptive,
+            strategy: Mode::Adaptive,
+            metadata: true,
+            generator: format!("carp-export-png {}", env!("CARGO_PKG_VERSION")),
+            thread_pool: None,
+        }
+    }
+}
 
 impl Export for PNGExporter {
     type Data = ImageBuf;
@@ -15,19 +118,40 @@ impl Export for PNGExporter {
     ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
         let start = std::time::Instant::now();
 
-        let (pixels, artifact) = artifact.extract_data();
+        let (image, artifact) = artifact.extract_data();
         let aspect_ratio = Some(AspectRatio::new(
-            pixels.width() as f64,
-            pixels.height() as f64,
+            image.width() as f64,
+            image.height() as f64,
         ));
 
         let mut header = Header::new();
-        header.set_size(pixels.width() as u32, pixels.height() as u32)?;
-        header.set_color(ColorType::TruecolorAlpha, 8)?;
-        let options = Options::new();
+        header.set_size(image.width() as u32, image.height() as u32)?;
+        let mut options = Options::new();
+        options.set_compression_level(self.compression)?;
+        options.set_filter_mode(self.filter)?;
+        options.set_strategy_mode(self.strategy)?;
+        if let Some(ref thread_pool) = self.thread_pool {
+            options.set_thread_pool(thread_pool)?;
+        }
+        let pixels = match self.alpha {
+            Alpha::Keep => {
+                header.set_color(ColorType::TruecolorAlpha, 8)?;
+                pixels::rgba(&image)
+            }
+            Alpha::Flatten(background) => {
+                header.set_color(ColorType::Truecolor, 8)?;
+                pixels::rgb(&image, background).into()
+            }
+        };
+
         let mut encoder = Encoder::new(Vec::new(), &options);
         encoder.write_header(&header)?;
-        encoder.write_image_rows(pixels.raw_pixels())?;
+        if self.metadata {
+            for (keyword, text) in self.text(&artifact, image.raw_pixels()) {
+                write_text(&mut encoder, keyword, &text)?;
+            }
+        }
+        encoder.write_image_rows(&pixels)?;
         let buf = encoder.finish()?;
 
         trace!(
@@ -43,3 +167,115 @@ impl Export for PNGExporter {
         })
     }
 }
+
+/// Writes `text` as a `tEXt` chunk, or as an `iTXt` chunk if it isn't plain ASCII.
+fn write_text<W: Write>(encoder: &mut Encoder<W>, keyword: &str, text: &str) -> io::Result<()> {
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    if text.is_ascii() {
+        data.extend_from_slice(text.as_bytes());
+        encoder.write_chunk(b"tEXt", &data)
+    } else {
+        // uncompressed, without language tag or translated keyword
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+        encoder.write_chunk(b"iTXt", &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use carp::{piet_common::ImageFormat, Backside, Side};
+    use png::{ColorType as PngColorType, Decoder};
+
+    use super::*;
+
+    /// A sheet of two pixels, opaque red and transparent.
+    fn artifact(deck: &str) -> Artifact<ImageBuf> {
+        Artifact {
+            deck: deck.into(),
+            shared: Backside::Shared,
+            data: ImageBuf::from_raw(
+                [255, 0, 0, 255, 0, 0, 0, 0].as_slice(),
+                ImageFormat::RgbaPremul,
+                2,
+                1,
+            ),
+            side: Side::Back,
+            content: Content::Sheet {
+                rows: 7,
+                columns: 10,
+                total: 69,
+            },
+            amount: Amount::Multiple { index: 2, total: 3 },
+            aspect_ratio: None,
+            extension: None,
+            preview: None,
+        }
+    }
+
+    /// The text chunks and pixels of a PNG file.
+    fn decode(data: &[u8]) -> (Vec<(String, String)>, PngColorType, Vec<u8>) {
+        let mut reader = Decoder::new(data).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut pixels).unwrap();
+        pixels.truncate(frame.buffer_size());
+
+        let info = reader.info();
+        let mut text: Vec<_> = info
+            .uncompressed_latin1_text
+            .iter()
+            .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+            .collect();
+        text.extend(
+            info.utf8_text
+                .iter()
+                .map(|chunk| (chunk.keyword.clone(), chunk.get_text().unwrap())),
+        );
+        (text, frame.color_type, pixels)
+    }
+
+    #[test]
+    fn metadata() {
+        let mut exporter = PNGExporter {
+            generator: "karten 1.0".into(),
+            ..Default::default()
+        };
+        let exported = exporter.export(artifact("Größe")).unwrap();
+        assert_eq!(exported.extension.as_deref(), Some("png"));
+
+        let (mut text, color_type, pixels) = decode(&exported.data);
+        assert_eq!(color_type, PngColorType::Rgba);
+        assert_eq!(pixels, [255, 0, 0, 255, 0, 0, 0, 0]);
+
+        text.sort();
+        let source = content_hash(&[255, 0, 0, 255, 0, 0, 0, 0]);
+        assert_eq!(
+            text,
+            [
+                ("Cards", "69"),
+                ("Grid", "7x10"),
+                ("Page", "2/3"),
+                ("Side", "back"),
+                ("Software", "karten 1.0"),
+                ("Source", source.as_str()),
+                // not ASCII, so it's an iTXt chunk
+                ("Title", "Größe"),
+            ]
+            .map(|(keyword, text)| (keyword.to_string(), text.to_string()))
+        );
+
+        exporter.metadata = false;
+        let (text, ..) = decode(&exporter.export(artifact("deck")).unwrap().data);
+        assert!(text.is_empty());
+    }
+
+    #[test]
+    fn flattening() {
+        let mut exporter = PNGExporter::new(Alpha::Flatten(Color::WHITE));
+        exporter.compression = CompressionLevel::High;
+        let (_, color_type, pixels) = decode(&exporter.export(artifact("deck")).unwrap().data);
+        assert_eq!(color_type, PngColorType::Rgb);
+        assert_eq!(pixels, [255, 0, 0, 255, 255, 255]);
+    }
+}
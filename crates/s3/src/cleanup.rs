@@ -0,0 +1,178 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Deletes the objects that the latest build of their deck doesn't use anymore.
+///
+/// Which deck uploaded which objects is kept in a [State] object in the bucket, so objects uploaded
+/// before the cleanup was turned on aren't known and never deleted. Decks that aren't part of a run are left alone,
+/// and nothing is deleted after a run with failures, as the old objects may be the last good copy of what failed.
+#[derive(Debug)]
+pub struct Cleanup {
+    /// Keep superseded objects for this long after a build last used them,
+    /// so links that were shared in the meantime keep working for a while.
+    pub retention: Duration,
+    /// Only log what would be deleted.
+    pub dry_run: bool,
+    /// The name of the [State] object, below [`S3Exporter::prefix`](crate::S3Exporter::prefix).
+    pub state: String,
+    pub(crate) uploads: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+impl Cleanup {
+    #[must_use]
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            dry_run: false,
+            state: "uploads.json".into(),
+            uploads: Mutex::default(),
+        }
+    }
+
+    /// Marks the objects uploaded in this run as used `now` in `state` and takes out the ones that expired.
+    ///
+    /// Unless the run is `complete` nothing expires, the expired objects stay in `state` for a later run.
+    pub fn expire(
+        &self,
+        state: &mut State,
+        now: u64,
+        complete: bool,
+    ) -> Result<Vec<Expired>, String> {
+        let uploads = self.uploads.lock().map_err(|e| e.to_string())?;
+        let expired = state.update(&uploads, now, self.retention.as_secs());
+        if complete {
+            return Ok(expired);
+        }
+        for expired in expired {
+            state.restore(expired);
+        }
+        Ok(Vec::new())
+    }
+}
+
+/// The objects every deck used, with the time a build last used them in seconds since the Unix epoch.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    pub decks: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+/// An object that is due for deletion.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Expired {
+    pub deck: String,
+    pub key: String,
+    pub used: u64,
+}
+
+impl State {
+    /// Marks the `uploads` of a run as used `now` and removes the objects of those decks
+    /// that weren't used for `retention` seconds, unless another deck still uses them.
+    pub fn update(
+        &mut self,
+        uploads: &HashMap<String, HashSet<String>>,
+        now: u64,
+        retention: u64,
+    ) -> Vec<Expired> {
+        let mut expired = Vec::new();
+        for (deck, keys) in uploads {
+            let known = self.decks.entry(deck.clone()).or_default();
+            for key in keys {
+                known.insert(key.clone(), now);
+            }
+            known.retain(|key, &mut used| {
+                let keep = now.saturating_sub(used) < retention;
+                if !keep {
+                    expired.push(Expired {
+                        deck: deck.clone(),
+                        key: key.clone(),
+                        used,
+                    });
+                }
+                keep
+            });
+        }
+
+        // identical images share their object with content addressed names
+        let used: HashSet<&String> = self.decks.values().flat_map(BTreeMap::keys).collect();
+        expired.retain(|expired| !used.contains(&expired.key));
+        expired.sort_by(|a, b| a.key.cmp(&b.key));
+        expired.dedup_by(|a, b| a.key == b.key);
+        expired
+    }
+
+    /// Puts `expired` back, e.g. because it couldn't be deleted.
+    pub fn restore(&mut self, expired: Expired) {
+        self.decks
+            .entry(expired.deck)
+            .or_default()
+            .insert(expired.key, expired.used);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uploads(deck: &str, keys: &[&str]) -> HashMap<String, HashSet<String>> {
+        HashMap::from([(
+            deck.to_string(),
+            keys.iter().map(ToString::to_string).collect(),
+        )])
+    }
+
+    #[test]
+    fn expiring() {
+        let mut state = State::default();
+        assert!(state
+            .update(&uploads("a", &["a1", "shared"]), 0, 10)
+            .is_empty());
+        assert!(state
+            .update(&uploads("b", &["b1", "shared"]), 0, 10)
+            .is_empty());
+
+        // superseded, but still within the retention window
+        assert!(state.update(&uploads("a", &["a2"]), 5, 10).is_empty());
+
+        let expired = state.update(&uploads("a", &["a2"]), 10, 10);
+        assert_eq!(
+            expired,
+            [Expired {
+                deck: "a".into(),
+                key: "a1".into(),
+                used: 0
+            }]
+        );
+        assert!(state.decks["a"].contains_key("a2"));
+        assert!(!state.decks["a"].contains_key("shared"));
+        assert!(state.decks["b"].contains_key("shared"));
+    }
+
+    #[test]
+    fn incomplete_runs_delete_nothing() {
+        let cleanup = Cleanup::new(Duration::from_secs(10));
+        *cleanup.uploads.lock().unwrap() = uploads("a", &["a2"]);
+        let mut state = State::default();
+        state.update(&uploads("a", &["a1"]), 0, 10);
+
+        assert!(cleanup.expire(&mut state, 20, false).unwrap().is_empty());
+        // the new upload is recorded, the expired one is kept for later
+        assert_eq!(state.decks["a"]["a1"], 0);
+        assert_eq!(state.decks["a"]["a2"], 20);
+
+        let expired = cleanup.expire(&mut state, 30, true).unwrap();
+        assert_eq!(
+            expired,
+            [Expired {
+                deck: "a".into(),
+                key: "a1".into(),
+                used: 0
+            }]
+        );
+        assert!(!state.decks["a"].contains_key("a1"));
+    }
+}
@@ -1,16 +1,308 @@
-use std::{path::{PathBuf, Path}, error::Error};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use carp::{export::Export, artifact::Artifact};
-use s3::Bucket;
+use carp::{
+    artifact::Artifact,
+    export::{content_type, encode, Export, Store, Template},
+    hash::content_hash,
+};
+use log::{debug, info, warn};
+use s3::{creds::Credentials, Bucket};
 use ulid::Ulid;
 
+mod cleanup;
+mod upload;
+
+pub use cleanup::{Cleanup, Expired, State};
+pub use upload::{UploadError, MIN_PART_SIZE};
+
+/// The metadata every object is uploaded with, holding the [content_hash] of its data.
+pub const CONTENT_HASH: &str = "content-hash";
+
 pub struct S3Exporter {
     pub bucket: Bucket,
+    /// How the objects are named.
+    /// Without a template every upload gets a new [Ulid], even if the same image was uploaded before,
+    /// unless [`S3Exporter::skip_unchanged`] is set.
+    /// Use [`Template::content_addressed`] to reuse names for identical images.
+    pub template: Option<Template>,
+    /// Put in front of every key, e.g. `decks/` to keep the uploads out of the bucket root.
+    pub prefix: String,
+    /// The `Cache-Control` header the objects are served with, e.g. `public, max-age=31536000, immutable`.
+    pub cache_control: Option<String>,
+    /// The canned ACL of the uploaded objects, e.g. `public-read`.
+    pub acl: Option<String>,
+    /// Don't upload objects that are already in the bucket with the same [CONTENT_HASH].
+    ///
+    /// Costs a HEAD request per object, but saves uploading sheets that didn't change.
+    /// Without a template, objects are named after their content, since a fresh name never exists yet.
+    pub skip_unchanged: bool,
+    /// Delete the objects the latest build doesn't use anymore once the run is finished.
+    pub cleanup: Option<Cleanup>,
+    /// Export presigned URLs that are valid for this long instead of public ones, for private buckets.
+    ///
+    /// S3 allows at most 7 days.
+    pub presign: Option<Duration>,
+    /// How often failed uploads are retried, if the failure looks temporary.
+    pub retries: u32,
+    /// How long to wait before the first retry, every further retry waits twice as long.
+    pub backoff: Duration,
+    /// Objects larger than this are uploaded in parts of this size, at least [MIN_PART_SIZE].
+    pub part_size: usize,
 }
 
 impl S3Exporter {
-    #[must_use] pub fn new(bucket: Bucket) -> Self {
-        Self { bucket }
+    #[must_use]
+    pub fn new(bucket: Bucket) -> Self {
+        Self {
+            bucket,
+            template: None,
+            prefix: String::new(),
+            cache_control: None,
+            acl: None,
+            skip_unchanged: false,
+            cleanup: None,
+            presign: None,
+            retries: 4,
+            backoff: Duration::from_millis(500),
+            part_size: 16 * 1024 * 1024,
+        }
+    }
+
+    /// The key `artifact` is uploaded under.
+    pub fn key<Format: AsRef<[u8]>>(&self, artifact: &Artifact<Format>) -> String {
+        let name = if let Some(ref template) = self.template {
+            template.render(artifact)
+        } else if self.skip_unchanged {
+            Template::content_addressed().render(artifact)
+        } else if let Some(ref extension) = artifact.extension {
+            format!("{}.{extension}", Ulid::new())
+        } else {
+            Ulid::new().to_string()
+        };
+        format!("{}{name}", self.prefix)
+    }
+
+    /// The public URL of the object at `key`.
+    #[must_use]
+    pub fn url(&self, key: &str) -> PathBuf {
+        let url = self.bucket.url();
+        format!("{}/{}", url.trim_end_matches('/'), encode(key)).into()
+    }
+
+    /// The URL the object at `key` is exported as, presigned if [`S3Exporter::presign`] is set.
+    pub fn location(&self, key: &str) -> Result<PathBuf, Box<dyn Error>> {
+        match self.presign {
+            Some(expiry) => {
+                let expiry = u32::try_from(expiry.as_secs()).unwrap_or(u32::MAX);
+                Ok(self.bucket.presign_get(key, expiry, None)?.into())
+            }
+            None => Ok(self.url(key)),
+        }
+    }
+
+    /// Checks that uploads will work before anything is rendered, by uploading a small probe object,
+    /// fetching it anonymously from its public URL and deleting it again.
+    /// With [`S3Exporter::presign`] set, the probe is fetched anonymously from its presigned URL instead,
+    /// which also catches URLs that are signed for the wrong region or endpoint.
+    ///
+    /// The error names the permission that is missing.
+    /// Not being allowed to delete the probe is only logged, as exporting doesn't need it.
+    pub fn preflight(&self) -> Result<(), Box<dyn Error>> {
+        let key = format!("{}preflight-{}.txt", self.prefix, Ulid::new());
+        let bucket = self.bucket.name();
+
+        let probe = b"preflight";
+        match self.upload(&key, probe, &content_hash(probe), content_type("txt")) {
+            Ok(()) => (),
+            Err(
+                error @ UploadError {
+                    status: Some(401 | 403),
+                    ..
+                },
+            ) => {
+                let mut permissions = "`s3:PutObject`".to_string();
+                if let Some(acl) = &self.acl {
+                    permissions += &format!(
+                        " and `s3:PutObjectAcl` to set the ACL {acl}, \
+                        which is denied as well if the bucket blocks public ACLs"
+                    );
+                }
+                return Err(
+                    format!("{error}. The credentials need the permission {permissions}").into(),
+                );
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        if self.presign.is_some() {
+            // fetched without credentials, like the Tabletop Simulator does
+            let result = self.location(&key).and_then(|url| {
+                let response = attohttpc::get(url.to_string_lossy())
+                    .timeout(Duration::from_secs(30))
+                    .send()?;
+                Ok(response.status().as_u16())
+            });
+            self.delete_probe(&key);
+            return match result {
+                Ok(200..=299) => {
+                    info!("{bucket} accepts uploads and presigned URLs can read them");
+                    Ok(())
+                }
+                Ok(status @ (401 | 403)) => Err(format!(
+                    "{bucket} denied reading {key} from its presigned URL (HTTP {status}). \
+                    Presigned URLs need the credentials to have the permission `s3:GetObject`, \
+                    and the region and endpoint have to be the ones of the bucket"
+                )
+                .into()),
+                Ok(status) => Err(format!(
+                    "couldn't read {key} from its presigned URL (HTTP {status})"
+                )
+                .into()),
+                Err(e) => Err(format!("couldn't read {key} from its presigned URL: {e}").into()),
+            };
+        }
+
+        let mut anonymous = self.bucket.clone();
+        anonymous.set_credentials(Credentials::anonymous()?);
+        let public = anonymous
+            .get_object(&key)
+            .map(|response| response.status_code());
+        self.delete_probe(&key);
+
+        match public {
+            Ok(200..=299) => {
+                info!("{bucket} accepts uploads and serves them publicly");
+                Ok(())
+            }
+            Ok(status @ (401 | 403)) => Err(format!(
+                "{} isn't publicly readable (HTTP {status}). \
+                Allow `s3:GetObject` for everyone in the bucket policy of {bucket} or set the ACL to public-read",
+                self.url(&key).display()
+            ).into()),
+            Ok(status) => Err(format!(
+                "couldn't fetch {} without credentials (HTTP {status})",
+                self.url(&key).display()
+            ).into()),
+            Err(e) => Err(format!(
+                "couldn't fetch {} without credentials: {e}",
+                self.url(&key).display()
+            ).into()),
+        }
+    }
+
+    /// Deletes the probe object of the [preflight](S3Exporter::preflight) check.
+    fn delete_probe(&self, key: &str) {
+        let bucket = self.bucket.name();
+        match self
+            .bucket
+            .delete_object(key)
+            .map(|response| response.status_code())
+        {
+            Ok(200..=299) => (),
+            Ok(status) => warn!(
+                "couldn't delete the probe {key} from {bucket} (HTTP {status}), \
+                does the account have the permission `s3:DeleteObject`?"
+            ),
+            Err(e) => warn!("couldn't delete the probe {key} from {bucket}: {e}"),
+        }
+    }
+
+    /// Deletes the objects that expired according to `cleanup` and stores the updated [State].
+    ///
+    /// Nothing is deleted unless the run is `complete`, see [`Cleanup::expire`].
+    fn clean_up(&self, cleanup: &Cleanup, complete: bool) -> Result<(), Box<dyn Error>> {
+        let bucket = self.bucket.name();
+        let key = format!("{}{}", self.prefix, cleanup.state);
+        let response = self.bucket.get_object(&key)?;
+        let mut state: State = match response.status_code() {
+            200 => serde_json::from_slice(response.as_slice())
+                .map_err(|e| format!("couldn't read {key} from {bucket}: {e}"))?,
+            404 => State::default(),
+            status => {
+                return Err(format!("couldn't load {key} from {bucket} (HTTP {status})").into())
+            }
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let expired = cleanup.expire(&mut state, now, complete)?;
+        if !complete {
+            info!("the run failed, so nothing is deleted from {bucket}");
+        }
+
+        if cleanup.dry_run {
+            for expired in &expired {
+                info!(
+                    "would delete {} of {} from {bucket}",
+                    expired.key, expired.deck
+                );
+            }
+            info!("would delete {} objects from {bucket}", expired.len());
+            return Ok(());
+        }
+
+        let mut deleted = 0;
+        for expired in expired {
+            match self
+                .bucket
+                .delete_object(&expired.key)
+                .map(|response| response.status_code())
+            {
+                Ok(200..=299) => {
+                    debug!("deleted {} of {} from {bucket}", expired.key, expired.deck);
+                    deleted += 1;
+                }
+                result => {
+                    match result {
+                        Ok(status) => warn!(
+                            "couldn't delete {} from {bucket} (HTTP {status})",
+                            expired.key
+                        ),
+                        Err(e) => warn!("couldn't delete {} from {bucket}: {e}", expired.key),
+                    }
+                    state.restore(expired);
+                }
+            }
+        }
+        info!("deleted {deleted} objects from {bucket} that aren't used anymore");
+
+        self.put(
+            &key,
+            &serde_json::to_vec_pretty(&state)?,
+            content_type("json"),
+        )
+    }
+
+    /// Whether the object at `key` exists and has the content `hash`.
+    fn unchanged(&self, key: &str, hash: &str) -> bool {
+        match self.bucket.head_object(key) {
+            Ok((head, 200)) => {
+                head.metadata
+                    .and_then(|mut metadata| metadata.remove(CONTENT_HASH))
+                    .as_deref()
+                    == Some(hash)
+            }
+            Ok(_) => false,
+            Err(e) => {
+                debug!("couldn't check whether {key} changed, uploading it anyway: {e}");
+                false
+            }
+        }
+    }
+
+    /// Uploads `data` to `key` and fails unless the bucket accepted it.
+    ///
+    /// Skips the upload if [`S3Exporter::skip_unchanged`] is set and the object is up to date.
+    fn put(&self, key: &str, data: &[u8], content_type: &str) -> Result<(), Box<dyn Error>> {
+        let hash = content_hash(data);
+        if self.skip_unchanged && self.unchanged(key, &hash) {
+            debug!("{key} is unchanged, skipping the upload");
+            return Ok(());
+        }
+        Ok(self.upload(key, data, &hash, content_type)?)
     }
 }
 
@@ -22,12 +314,79 @@ impl Export for S3Exporter {
         &self,
         artifact: Artifact<Self::Data>,
     ) -> std::result::Result<Artifact<Self::Output>, Box<dyn Error>> {
-        let filename = if let Some(ref extension) = artifact.extension {
-            format!("{}.{extension}", Ulid::new())
-        } else {
-            Ulid::new().to_string()
+        let key = self.key(&artifact);
+        let content_type = content_type(artifact.extension.as_deref().unwrap_or_default());
+        self.put(&key, &artifact.data, content_type)?;
+        if let Some(ref cleanup) = self.cleanup {
+            cleanup
+                .uploads
+                .lock()
+                .map_err(|e| e.to_string())?
+                .entry(artifact.deck.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        let location = self.location(&key)?;
+        Ok(artifact.with_data(location))
+    }
+
+    /// Records which objects the run used, but only deletes expired ones if the run is `complete`.
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        match self.cleanup {
+            Some(ref cleanup) => self.clean_up(cleanup, complete),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Store for S3Exporter {
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        let key = format!("{}{name}", self.prefix);
+        let extension = Path::new(name).extension().unwrap_or_default();
+        self.put(&key, data, content_type(&extension.to_string_lossy()))?;
+        self.location(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use carp::{
+        artifact::{Amount, Content},
+        Backside, Side,
+    };
+    use s3::Region;
+
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let region = Region::Custom {
+            region: "us-east-1".into(),
+            endpoint: "http://localhost:9000".into(),
+        };
+        let bucket = Bucket::new("decks", region, Credentials::anonymous().unwrap())
+            .unwrap()
+            .with_path_style();
+        let mut exporter = S3Exporter::new(bucket);
+        exporter.template = Some("{deck}/{side}.{ext}".parse().unwrap());
+        exporter.prefix = "tts/".into();
+
+        let artifact = Artifact {
+            deck: "Base Game #1".into(),
+            shared: Backside::Shared,
+            data: b"image".to_vec(),
+            side: Side::Front,
+            content: Content::Single,
+            amount: Amount::Single,
+            aspect_ratio: None,
+            extension: Some("png".into()),
+            preview: None,
         };
-        self.bucket.put_object(filename.clone(), &artifact.data)?;
-        Ok(artifact.with_data(Path::new(&self.bucket.url()).join(filename)))
+        let key = exporter.key(&artifact);
+        assert_eq!(key, "tts/Base Game #1/front.png");
+        assert_eq!(
+            exporter.location(&key).unwrap(),
+            PathBuf::from("http://localhost:9000/decks/tts/Base%20Game%20%231/front.png")
+        );
     }
 }
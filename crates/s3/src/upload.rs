@@ -0,0 +1,303 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    thread,
+    time::Duration,
+};
+
+use log::warn;
+use s3::{error::S3Error, Bucket};
+
+use crate::{S3Exporter, CONTENT_HASH};
+
+/// The smallest part S3 accepts in a multipart upload, apart from the last one.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// An upload that failed, even after retrying.
+#[derive(Debug)]
+pub struct UploadError {
+    pub bucket: String,
+    pub key: String,
+    /// The HTTP status the bucket answered with, if it answered at all.
+    pub status: Option<u16>,
+    pub message: String,
+    /// Whether trying again might help, e.g. after a timeout or a 503 Slow Down.
+    pub transient: bool,
+}
+
+impl UploadError {
+    fn new(bucket: &Bucket, key: &str, error: S3Error) -> Self {
+        let (status, message) = match error {
+            S3Error::HttpFailWithBody(status, ref body) => (
+                Some(status),
+                reason(body).unwrap_or_else(|| {
+                    let body = body.trim();
+                    if body.is_empty() {
+                        "the request was rejected".into()
+                    } else {
+                        body.chars().take(200).collect()
+                    }
+                }),
+            ),
+            _ => (None, error.to_string()),
+        };
+        Self {
+            bucket: bucket.name(),
+            key: key.into(),
+            status,
+            message,
+            transient: match status {
+                Some(status) => matches!(status, 408 | 429 | 500..),
+                None => matches!(error, S3Error::Io(_) | S3Error::Atto(_)),
+            },
+        }
+    }
+
+    fn status(bucket: &Bucket, key: &str, status: u16, body: &[u8]) -> Self {
+        let body = String::from_utf8_lossy(body);
+        Self::new(bucket, key, S3Error::HttpFailWithBody(status, body.into()))
+    }
+}
+
+impl Display for UploadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "couldn't upload {} to {}", self.key, self.bucket)?;
+        if let Some(status) = self.status {
+            write!(f, " (HTTP {status})")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Error for UploadError {}
+
+/// The `<Code>` and `<Message>` of an S3 error response.
+fn reason(body: &str) -> Option<String> {
+    let element = |name: &str| {
+        let start = body.find(&format!("<{name}>"))? + name.len() + 2;
+        let end = body[start..].find(&format!("</{name}>"))? + start;
+        Some(&body[start..end])
+    };
+    match (element("Code"), element("Message")) {
+        (Some(code), Some(message)) => Some(format!("{code}: {message}")),
+        (code, message) => code.or(message).map(Into::into),
+    }
+}
+
+impl S3Exporter {
+    /// Uploads `data` with the content `hash` to `key` with the configured headers,
+    /// retrying transient failures and splitting large objects into parts.
+    pub(crate) fn upload(
+        &self,
+        key: &str,
+        data: &[u8],
+        hash: &str,
+        content_type: &str,
+    ) -> Result<(), UploadError> {
+        let mut bucket = self.bucket.clone();
+        bucket.add_header(&format!("x-amz-meta-{CONTENT_HASH}"), hash);
+        for (name, value) in [
+            ("cache-control", &self.cache_control),
+            ("x-amz-acl", &self.acl),
+        ] {
+            if let Some(value) = value {
+                // `add_header` panics on values that aren't valid in a header
+                if !value
+                    .bytes()
+                    .all(|b| b == b'\t' || (b' '..=b'~').contains(&b))
+                {
+                    return Err(UploadError {
+                        bucket: bucket.name(),
+                        key: key.into(),
+                        status: None,
+                        message: format!("invalid {name} header: {value:?}"),
+                        transient: false,
+                    });
+                }
+                bucket.add_header(name, value);
+            }
+        }
+
+        retry(self.retries, self.backoff, || {
+            if data.len() > self.part_size.max(MIN_PART_SIZE) {
+                self.upload_parts(&bucket, key, data, content_type)
+            } else {
+                match bucket.put_object_with_content_type(key, data, content_type) {
+                    Ok(response) if (200..300).contains(&response.status_code()) => Ok(()),
+                    // the body of a failed upload isn't kept, only its ETag header would be
+                    Ok(response) => Err(UploadError::status(
+                        &bucket,
+                        key,
+                        response.status_code(),
+                        b"",
+                    )),
+                    Err(e) => Err(UploadError::new(&bucket, key, e)),
+                }
+            }
+        })
+    }
+
+    /// Uploads `data` in parts of [`S3Exporter::part_size`], so large sheets don't need to go through in one request.
+    ///
+    /// The upload is initiated through `with_headers`, which sets the metadata and ACL of the object.
+    /// The parts don't need them, so every other request goes through [`S3Exporter::bucket`].
+    fn upload_parts(
+        &self,
+        with_headers: &Bucket,
+        key: &str,
+        data: &[u8],
+        content_type: &str,
+    ) -> Result<(), UploadError> {
+        let bucket = &self.bucket;
+        let upload = with_headers
+            .initiate_multipart_upload(key, content_type)
+            .map_err(|e| UploadError::new(bucket, key, e))?;
+
+        let mut parts = Vec::new();
+        for (index, chunk) in data.chunks(self.part_size.max(MIN_PART_SIZE)).enumerate() {
+            match bucket.put_multipart_chunk(
+                chunk.to_vec(),
+                key,
+                index as u32 + 1,
+                &upload.upload_id,
+                content_type,
+            ) {
+                Ok(part) => parts.push(part),
+                Err(e) => {
+                    // failed parts abort the upload themselves, but errors before a response don't
+                    let _ = bucket.abort_upload(key, &upload.upload_id);
+                    return Err(UploadError::new(bucket, key, e));
+                }
+            }
+        }
+
+        let response = bucket
+            .complete_multipart_upload(key, &upload.upload_id, parts)
+            .map_err(|e| UploadError::new(bucket, key, e))?;
+        // S3 reports some failures with a 200 and an error in the body
+        if !(200..300).contains(&response.status_code())
+            || response
+                .as_slice()
+                .windows(7)
+                .any(|window| window == b"<Error>")
+        {
+            let _ = bucket.abort_upload(key, &upload.upload_id);
+            let status = if response.status_code() == 200 {
+                500
+            } else {
+                response.status_code()
+            };
+            return Err(UploadError::status(
+                bucket,
+                key,
+                status,
+                response.as_slice(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Runs `upload` until it succeeds, fails for good or was retried `retries` times.
+///
+/// Waits `backoff` before the first retry and twice as long before every further one, at most a minute.
+fn retry(
+    retries: u32,
+    backoff: Duration,
+    mut upload: impl FnMut() -> Result<(), UploadError>,
+) -> Result<(), UploadError> {
+    let mut delay = backoff;
+    let mut attempt = 0;
+    loop {
+        match upload() {
+            Err(error) if error.transient && attempt < retries => {
+                attempt += 1;
+                warn!("{error}, retrying in {delay:?} ({attempt}/{retries})");
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2).min(Duration::from_secs(60));
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use s3::{creds::Credentials, Region};
+
+    use super::*;
+
+    fn error(error: S3Error) -> UploadError {
+        let region = Region::Custom {
+            region: "us-east-1".into(),
+            endpoint: "http://localhost:9000".into(),
+        };
+        let bucket = Bucket::new("decks", region, Credentials::anonymous().unwrap()).unwrap();
+        UploadError::new(&bucket, "deck.png", error)
+    }
+
+    #[test]
+    fn transient() {
+        let slow_down = error(S3Error::HttpFailWithBody(
+            503,
+            "<Error><Code>SlowDown</Code></Error>".into(),
+        ));
+        assert!(slow_down.transient);
+        assert_eq!(slow_down.status, Some(503));
+        assert_eq!(
+            slow_down.to_string(),
+            "couldn't upload deck.png to decks (HTTP 503): SlowDown"
+        );
+
+        assert!(error(S3Error::HttpFailWithBody(429, String::new())).transient);
+        assert!(error(S3Error::HttpFailWithBody(500, String::new())).transient);
+        assert!(
+            error(S3Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out"
+            )))
+            .transient
+        );
+
+        let denied = error(S3Error::HttpFailWithBody(403, String::new()));
+        assert!(!denied.transient);
+        assert_eq!(denied.message, "the request was rejected");
+        assert!(!error(S3Error::HttpFailWithBody(404, String::new())).transient);
+    }
+
+    #[test]
+    fn retrying() {
+        let attempts = |failures: usize, status: u16| {
+            let mut attempts = 0;
+            let result = retry(3, Duration::ZERO, || {
+                attempts += 1;
+                match attempts <= failures {
+                    true => Err(error(S3Error::HttpFailWithBody(status, String::new()))),
+                    false => Ok(()),
+                }
+            });
+            (result.is_ok(), attempts)
+        };
+
+        assert_eq!(attempts(0, 503), (true, 1));
+        assert_eq!(attempts(2, 503), (true, 3));
+        // gives up after 3 retries
+        assert_eq!(attempts(10, 503), (false, 4));
+        // doesn't retry what can't work
+        assert_eq!(attempts(10, 403), (false, 1));
+    }
+
+    #[test]
+    fn reasons() {
+        let body = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>AccessDenied</Code>\
+            <Message>Access Denied</Message><RequestId>42</RequestId></Error>";
+        assert_eq!(reason(body).as_deref(), Some("AccessDenied: Access Denied"));
+        assert_eq!(
+            reason("<Error><Code>SlowDown</Code></Error>").as_deref(),
+            Some("SlowDown")
+        );
+        assert_eq!(reason("Bad Gateway"), None);
+    }
+}
@@ -0,0 +1,94 @@
+//! Runs the exporter against a local S3 compatible server.
+//!
+//! Start one with `docker run -p 9000:9000 minio/minio server /data` and run
+//! `cargo test -p carp-export-s3 -- --ignored`. `MINIO_ENDPOINT`, `MINIO_ACCESS_KEY` and `MINIO_SECRET_KEY`
+//! point the tests at another server.
+
+use std::{env, path::Path};
+
+use carp::{
+    artifact::{Amount, Artifact, Content},
+    export::Export,
+    Backside, Side,
+};
+use carp_export_s3::{S3Exporter, UploadError, MIN_PART_SIZE};
+use s3::{creds::Credentials, Bucket, BucketConfiguration, Region};
+
+fn bucket(name: &str) -> Bucket {
+    let region = Region::Custom {
+        region: "us-east-1".into(),
+        endpoint: env::var("MINIO_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".into()),
+    };
+    let credentials = Credentials::new(
+        Some(&env::var("MINIO_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".into())),
+        Some(&env::var("MINIO_SECRET_KEY").unwrap_or_else(|_| "minioadmin".into())),
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    Bucket::new(name, region, credentials)
+        .unwrap()
+        .with_path_style()
+}
+
+fn created(name: &str) -> Bucket {
+    let bucket = bucket(name);
+    // fails if the bucket exists already, which is fine
+    let _ = Bucket::create_with_path_style(
+        name,
+        bucket.region(),
+        bucket.credentials().unwrap(),
+        BucketConfiguration::default(),
+    );
+    bucket
+}
+
+fn artifact(data: Vec<u8>) -> Artifact<Vec<u8>> {
+    Artifact {
+        deck: "minio".into(),
+        shared: Backside::Shared,
+        data,
+        side: Side::Front,
+        content: Content::Single,
+        amount: Amount::Single,
+        aspect_ratio: None,
+        extension: Some("png".into()),
+        preview: None,
+    }
+}
+
+fn key(location: &Path) -> String {
+    location.file_name().unwrap().to_string_lossy().into()
+}
+
+#[test]
+#[ignore = "needs a local S3 compatible server"]
+fn uploads() {
+    let mut exporter = S3Exporter::new(created("carp-export-s3-test"));
+    exporter.part_size = MIN_PART_SIZE;
+
+    for size in [1024, 2 * MIN_PART_SIZE + 1] {
+        let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let exported = exporter.export(artifact(data.clone())).unwrap();
+
+        let object = exporter.bucket.get_object(key(&exported.data)).unwrap();
+        assert_eq!(object.status_code(), 200);
+        assert_eq!(object.as_slice(), data, "{size} bytes");
+        assert_eq!(object.headers()["content-type"], "image/png");
+    }
+}
+
+#[test]
+#[ignore = "needs a local S3 compatible server"]
+fn errors_name_bucket_key_and_status() {
+    let mut exporter = S3Exporter::new(bucket("carp-export-s3-missing"));
+    exporter.prefix = "prefix/".into();
+
+    let error = exporter.export(artifact(vec![0; 16])).unwrap_err();
+    let error = error.downcast_ref::<UploadError>().unwrap();
+    assert_eq!(error.bucket, "carp-export-s3-missing");
+    assert!(error.key.starts_with("prefix/"));
+    assert_eq!(error.status, Some(404));
+    assert!(!error.transient);
+}
@@ -0,0 +1,111 @@
+use std::error::Error;
+
+use carp::{
+    artifact::Artifact, dimensions::AspectRatio, export::Export, piet_common::ImageBuf, pixels,
+};
+use log::trace;
+use webp::Encoder;
+
+/// Encodes rendered images as lossy WebP files, keeping transparency.
+pub struct WebPExporter {
+    /// From 1 (smallest files) to 100 (best quality).
+    pub quality: u8,
+}
+
+impl WebPExporter {
+    #[must_use]
+    pub fn new(quality: u8) -> Self {
+        Self { quality }
+    }
+}
+
+impl Default for WebPExporter {
+    fn default() -> Self {
+        Self { quality: 90 }
+    }
+}
+
+impl Export for WebPExporter {
+    type Data = ImageBuf;
+    type Output = Vec<u8>;
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        let start = std::time::Instant::now();
+
+        let (image, artifact) = artifact.extract_data();
+        let aspect_ratio = Some(AspectRatio::new(
+            image.width() as f64,
+            image.height() as f64,
+        ));
+
+        let pixels = pixels::rgba(&image);
+        let buf = Encoder::from_rgba(&pixels, image.width() as u32, image.height() as u32)
+            .encode_simple(false, f32::from(self.quality))
+            .map_err(|e| format!("couldn't encode WebP: {e:?}"))?
+            .to_vec();
+
+        trace!(
+            "Exported {:?}.webp in {:?}",
+            artifact.to_string(),
+            start.elapsed()
+        );
+
+        Ok(Artifact {
+            aspect_ratio,
+            extension: Some("webp".into()),
+            ..artifact.with_data(buf)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use carp::{
+        artifact::{Amount, Content},
+        piet_common::ImageFormat,
+        Backside, Side,
+    };
+    use webp::Decoder;
+
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        // opaque red on the left, transparent on the right
+        let pixels: Vec<u8> = (0..8 * 16)
+            .flat_map(|index| {
+                if index % 16 < 8 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 0, 0]
+                }
+            })
+            .collect();
+        let artifact = Artifact {
+            deck: "deck".into(),
+            shared: Backside::Shared,
+            data: ImageBuf::from_raw(pixels, ImageFormat::RgbaPremul, 16, 8),
+            side: Side::Front,
+            content: Content::Single,
+            amount: Amount::Single,
+            aspect_ratio: None,
+            extension: None,
+            preview: None,
+        };
+
+        let exported = WebPExporter::default().export(artifact).unwrap();
+        assert_eq!(exported.extension.as_deref(), Some("webp"));
+        assert_eq!(exported.aspect_ratio, Some(AspectRatio(2.0)));
+
+        let image = Decoder::new(&exported.data).decode().unwrap();
+        assert_eq!((image.width(), image.height()), (16, 8));
+        assert!(image.is_alpha());
+        let pixel = |x: usize| &image[x * 4..x * 4 + 4];
+        let red = pixel(0);
+        assert!(red[0] > 240 && red[1] < 16 && red[2] < 16, "{red:?}");
+        assert_eq!(red[3], 255);
+        assert_eq!(pixel(15)[3], 0);
+    }
+}
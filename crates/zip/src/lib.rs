@@ -0,0 +1,267 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+};
+
+use carp::{
+    artifact::Artifact,
+    export::{Export, Store, Template},
+    hash::content_hash,
+};
+use log::{debug, info, warn};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+/// Collects every artifact of a run into a single `.zip` file.
+///
+/// The archive is written next to [`ZipExporter::path`] and only moved there once the run is finished.
+/// If the run failed, the previous archive is kept and the new one is moved to [`ZipExporter::incomplete`] instead.
+/// Wrap it in a [`Manifest`](carp::export::Manifest) to include a `manifest.json`
+/// that lists which file belongs to which deck.
+///
+/// The output of every artifact is its name inside the archive.
+pub struct ZipExporter {
+    /// Where the finished archive ends up.
+    pub path: PathBuf,
+    /// How the files inside the archive are named.
+    pub template: Template,
+    temporary: PathBuf,
+    archive: Mutex<Archive>,
+}
+
+struct Archive {
+    /// `None` once the archive is finished.
+    writer: Option<ZipWriter<File>>,
+    /// The [content_hash] of every file in the archive, by name.
+    names: HashMap<String, String>,
+}
+
+impl ZipExporter {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        let mut temporary = OsString::from(path.as_os_str());
+        temporary.push(format!(".{}.tmp", std::process::id()));
+        let temporary = PathBuf::from(temporary);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let writer = ZipWriter::new(File::create(&temporary)?);
+
+        Ok(Self {
+            path,
+            template: Template::default(),
+            temporary,
+            archive: Mutex::new(Archive {
+                writer: Some(writer),
+                names: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Where the archive of a run with failures ends up, e.g. `export.incomplete.zip`.
+    #[must_use]
+    pub fn incomplete(&self) -> PathBuf {
+        match self.path.extension() {
+            Some(extension) => {
+                let mut incomplete = OsString::from("incomplete.");
+                incomplete.push(extension);
+                self.path.with_extension(incomplete)
+            }
+            None => self.path.with_extension("incomplete"),
+        }
+    }
+
+    /// Adds `data` as `name`, unless the same data is in the archive under that name already.
+    fn add(
+        &self,
+        name: &str,
+        data: &[u8],
+        method: CompressionMethod,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut archive = self.archive.lock().map_err(|e| e.to_string())?;
+        let hash = content_hash(data);
+        match archive.names.get(name) {
+            // e.g. identical images with content addressed names
+            Some(existing) if *existing == hash => {
+                debug!("{name} is already in the archive");
+                return Ok(());
+            }
+            Some(_) => {
+                return Err(format!(
+                    "{name} is already in the archive with different contents, \
+                    does the name template tell the artifacts apart?"
+                )
+                .into())
+            }
+            None => archive.names.insert(name.into(), hash),
+        };
+        let writer = archive
+            .writer
+            .as_mut()
+            .ok_or("the archive is already finished")?;
+        writer.start_file(name, FileOptions::default().compression_method(method))?;
+        writer.write_all(data)?;
+        Ok(())
+    }
+}
+
+impl Drop for ZipExporter {
+    /// Removes the temporary archive if the run never finished, e.g. because of an error.
+    fn drop(&mut self) {
+        let archive = self
+            .archive
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(writer) = archive.writer.take() {
+            // closes the file, Windows can't remove open files
+            drop(writer);
+            let _ = fs::remove_file(&self.temporary);
+        }
+    }
+}
+
+impl Export for ZipExporter {
+    type Data = Vec<u8>;
+    type Output = PathBuf;
+
+    fn export(
+        &self,
+        artifact: Artifact<Self::Data>,
+    ) -> Result<Artifact<Self::Output>, Box<dyn Error>> {
+        let name = self.template.render(&artifact);
+        // images are compressed already
+        self.add(&name, &artifact.data, CompressionMethod::Stored)?;
+        Ok(artifact.with_data(name.into()))
+    }
+
+    /// Replaces the previous archive only if the run is `complete`.
+    fn finish(&self, complete: bool) -> Result<(), Box<dyn Error>> {
+        let mut archive = self.archive.lock().map_err(|e| e.to_string())?;
+        if let Some(mut writer) = archive.writer.take() {
+            writer.finish()?.sync_all()?;
+            let path = if complete {
+                self.path.clone()
+            } else {
+                self.incomplete()
+            };
+            fs::rename(&self.temporary, &path)?;
+            if complete {
+                info!("Wrote {} files to {}", archive.names.len(), path.display());
+            } else {
+                warn!(
+                    "The run failed, so {} is kept and the {} files are in {}",
+                    self.path.display(),
+                    archive.names.len(),
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Store for ZipExporter {
+    fn store(&self, name: &str, data: &[u8]) -> Result<Self::Output, Box<dyn Error>> {
+        self.add(name, data, CompressionMethod::Deflated)?;
+        Ok(name.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use carp::{
+        artifact::{Amount, Content},
+        Backside, Side,
+    };
+    use zip::ZipArchive;
+
+    use super::*;
+
+    fn artifact(deck: &str) -> Artifact<Vec<u8>> {
+        Artifact {
+            deck: deck.into(),
+            shared: Backside::Shared,
+            data: b"image".to_vec(),
+            side: Side::Front,
+            content: Content::Single,
+            amount: Amount::Single,
+            aspect_ratio: None,
+            extension: Some("png".into()),
+            preview: None,
+        }
+    }
+
+    #[test]
+    fn archiving() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("export.zip");
+        let exporter = ZipExporter::new(path.clone()).unwrap();
+        let exported = exporter.export(artifact("deck")).unwrap();
+        assert_eq!(exported.data, PathBuf::from("deck-front-single-1of1.png"));
+        exporter.store("manifest.json", b"{}").unwrap();
+        assert!(!path.exists());
+        exporter.finish(true).unwrap();
+        drop(exporter);
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort_unstable();
+        assert_eq!(names, ["deck-front-single-1of1.png", "manifest.json"]);
+        let mut data = Vec::new();
+        archive
+            .by_name("deck-front-single-1of1.png")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"image");
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn duplicates() {
+        let directory = tempfile::tempdir().unwrap();
+        let exporter = ZipExporter::new(directory.path().join("export.zip")).unwrap();
+        exporter.store("front.png", b"image").unwrap();
+        // identical files share a name with content addressed templates
+        exporter.store("front.png", b"image").unwrap();
+        let error = exporter.store("front.png", b"other image").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "front.png is already in the archive with different contents, \
+            does the name template tell the artifacts apart?"
+        );
+    }
+
+    #[test]
+    fn incomplete() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("export.zip");
+        fs::write(&path, b"the last good archive").unwrap();
+
+        let exporter = ZipExporter::new(path.clone()).unwrap();
+        exporter.export(artifact("deck")).unwrap();
+        exporter.finish(false).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"the last good archive");
+        let incomplete = directory.path().join("export.incomplete.zip");
+        assert_eq!(exporter.incomplete(), incomplete);
+        let archive = ZipArchive::new(File::open(incomplete).unwrap()).unwrap();
+        assert_eq!(archive.len(), 1);
+    }
+
+    #[test]
+    fn unfinished() {
+        let directory = tempfile::tempdir().unwrap();
+        let exporter = ZipExporter::new(directory.path().join("export.zip")).unwrap();
+        exporter.export(artifact("deck")).unwrap();
+        drop(exporter);
+
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+}